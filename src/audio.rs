@@ -0,0 +1,74 @@
+use std::f32::consts::PI;
+
+use clap::ValueEnum;
+use sdl2::audio::AudioCallback;
+
+use rust_chip_8::Tone;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+/// How the plain CHIP-8 beep sounds
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// 0.0 to 1.0
+    pub volume: f32,
+}
+
+/// The SDL audio callback, generating samples for whatever tone the emulator last asked for
+pub struct Voice {
+    pub settings: AudioSettings,
+    pub tone: Option<Tone>,
+    sample_rate: f32,
+    phase: f32,
+}
+
+impl Voice {
+    pub fn new(settings: AudioSettings, sample_rate: i32) -> Self {
+        Self {
+            settings,
+            tone: None,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+        }
+    }
+
+    fn beep_sample(&mut self) -> f32 {
+        self.phase = (self.phase + self.settings.frequency / self.sample_rate) % 1.0;
+        match self.settings.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+        }
+    }
+
+    /// Play the XO-CHIP pattern one bit at a time, phase counts bits from 0 to 128
+    fn pattern_sample(&mut self, pattern: &[u8; 16], pitch: u8) -> f32 {
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        self.phase = (self.phase + rate / self.sample_rate) % 128.0;
+
+        let bit_idx = self.phase as usize;
+        let bit = (pattern[bit_idx / 8] >> (7 - bit_idx % 8)) & 0x1;
+        if bit == 1 { 1.0 } else { -1.0 }
+    }
+}
+
+impl AudioCallback for Voice {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = match self.tone {
+                None => 0.0,
+                Some(Tone::Beep) => self.beep_sample() * self.settings.volume,
+                Some(Tone::Pattern { pattern, pitch }) => self.pattern_sample(&pattern, pitch) * self.settings.volume,
+            };
+        }
+    }
+}
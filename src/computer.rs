@@ -1,13 +1,19 @@
 
-use rand::Rng;
-
-use crate::keyboard::Keyboard;
+use crate::frontend::{AudioOutput, FrameSink, Keypad, Tone};
 use crate::memory::Memory;
 use crate::display::Display;
+use crate::error::EmulatorError;
 use crate::instruction::Instruction;
-use crate::sdl_system::SdlSystem;
+use crate::opcode::Op;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::rng::{Rng, RngMode};
+use crate::save_state::{StateReader, StateWriter};
 use crate::stack::Stack;
 use crate::timer::Timer;
+use crate::timing::{vip_cycles, vip_draw_cycles, Timing, VIP_CYCLES_PER_FRAME, VIP_DISPLAY_DMA_CYCLES, VIP_MACHINE_CYCLES_PER_SECOND};
+use crate::trace::Trace;
+use crate::watchpoint::{Access, WatchHit, Watchpoint};
 
 use std::fs;
 use std::path::Path;
@@ -31,27 +37,53 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
-const KEY_MAP: [sdl2::keyboard::Keycode; 16] = [
-    sdl2::keyboard::Keycode::X, // 0
-    sdl2::keyboard::Keycode::Num1, // 1
-    sdl2::keyboard::Keycode::Num2, // 2
-    sdl2::keyboard::Keycode::Num3,  // 3
-    sdl2::keyboard::Keycode::Q, // 4
-    sdl2::keyboard::Keycode::W, // 5
-    sdl2::keyboard::Keycode::E, // 6
-    sdl2::keyboard::Keycode::A, // 7
-    sdl2::keyboard::Keycode::S, // 8
-    sdl2::keyboard::Keycode::D, // 9
-    sdl2::keyboard::Keycode::Z, // A
-    sdl2::keyboard::Keycode::C, // B
-    sdl2::keyboard::Keycode::Num4, // C
-    sdl2::keyboard::Keycode::R, // D
-    sdl2::keyboard::Keycode::F, // E
-    sdl2::keyboard::Keycode::V, // F
+// SUPER-CHIP 8x10 font used by FX30
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
 ];
 
 const FONT_MEMORY_START: usize = 0x50;
+const BIG_FONT_MEMORY_START: usize = 0xA0;
 const ROM_START: usize = 0x200;
+const DEFAULT_PITCH: u8 = 64; // 4000Hz playback rate for the XO-CHIP audio pattern
+pub(crate) const FRAME_TIME: f32 = 1.0 / 60.0;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 11; // 660Hz
+
+/// Emulates a machine code routine, given the computer and the address called
+pub type MachineCallHook = Box<dyn FnMut(&mut Computer, u16) -> Result<(), EmulatorError>>;
+
+/// What to do with a 0NNN call to COSMAC VIP machine code, which can't be emulated
+pub enum MachineCallPolicy {
+    /// Carry on with the next instruction, what most interpreters since the VIP do
+    Ignore,
+    /// Stop with `EmulatorError::MachineCall`
+    Error,
+    /// Call a function with the address, to emulate the routines a ROM relies on
+    Hook(MachineCallHook),
+}
+
+/// FX0A waiting for a key to go down and come back up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct KeyWait {
+    x: usize,
+    /// The key once it has gone down
+    key: Option<u8>,
+}
 
 pub struct Computer {
     memory: Memory,
@@ -64,223 +96,839 @@ pub struct Computer {
     program_counter: usize,
     index_register: usize,
     registers: [u8; 16],
+    rpl_flags: [u8; 16],
+
+    audio_pattern: [u8; 16],
+    audio_pattern_loaded: bool,
+    pitch: u8,
+
+    platform: Platform,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    instruction_budget: f32,
+    timing: Timing,
+    /// Machine cycles due to run with VIP timing
+    cycle_budget: f32,
+    /// Machine cycles since the last 60Hz interrupt with VIP timing
+    frame_cycles: u32,
+    waiting_for_vblank: bool,
+    /// With VIP timing DXYN waits for the interrupt and draws then, this is its X, Y and N
+    pending_draw: Option<(usize, usize, u8)>,
+    waiting_for_key: Option<KeyWait>,
+    exited: bool,
+    machine_calls: MachineCallPolicy,
+
+    watchpoints: Vec<Watchpoint>,
+    watch_hits: Vec<WatchHit>,
+    /// Address and decoded form of the instruction being executed, for watch hits
+    executing: (usize, Op),
+    instructions: u64,
+    rng: Rng,
+    trace: Option<Trace>,
 }
 
 impl Computer {
-    pub fn new() -> Self {
-        let mut memory = Memory::new();
-        memory.load(FONT_MEMORY_START, &FONT);
+    pub fn new(platform: Platform, quirks: Quirks) -> Self {
+        let mut memory = Memory::new(platform.memory_size());
+        memory.load(FONT_MEMORY_START, &FONT).unwrap();
+        memory.load(BIG_FONT_MEMORY_START, &BIG_FONT).unwrap();
 
         Self {
             memory,
             display: Display::new(),
-            stack: Stack::new(),
+            stack: Stack::new(platform.stack_depth()),
             delay_timer: Timer::new(),
             sound_timer: Timer::new(),
             program_counter: 0,
             index_register: 0,
             registers: [0; 16],
+            rpl_flags: [0; 16],
+            audio_pattern: [0; 16],
+            audio_pattern_loaded: false,
+            pitch: DEFAULT_PITCH,
+            platform,
+            quirks,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            instruction_budget: 0.0,
+            timing: Timing::Instructions,
+            cycle_budget: 0.0,
+            frame_cycles: 0,
+            waiting_for_vblank: false,
+            pending_draw: None,
+            waiting_for_key: None,
+            exited: false,
+            machine_calls: MachineCallPolicy::Ignore,
+            watchpoints: vec![],
+            watch_hits: vec![],
+            executing: (0, Op::Unknown(0)),
+            instructions: 0,
+            rng: Rng::from_entropy(RngMode::default()),
+            trace: None,
         }
     }
 
-    pub fn load_program_from_file(&mut self, path: &Path) {
-        let data: Vec<u8> = fs::read(path).unwrap();
-        self.memory.load(ROM_START, &data);
+    pub fn load_program_from_file(&mut self, path: &Path) -> Result<(), EmulatorError> {
+        let data: Vec<u8> = fs::read(path)?;
+        self.load_program(&data)
+    }
+
+    /// Copy a ROM into memory at 0x200 and start executing it from there
+    pub fn load_program(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let max = self.memory.size() - ROM_START;
+        if data.len() > max {
+            return Err(EmulatorError::RomTooLarge { size: data.len(), max });
+        }
+
+        self.memory.load(ROM_START, data)?;
         self.program_counter = ROM_START;
+        Ok(())
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    /// Set the CPU speed, the number of instructions executed for each 60Hz frame
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    pub fn index_register(&self) -> usize {
+        self.index_register
+    }
+
+    pub fn set_index_register(&mut self, value: usize) {
+        self.index_register = value;
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, address: usize) {
+        self.program_counter = address;
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// Change how deep subroutine calls can nest, the platform decides by default
+    pub fn set_stack_depth(&mut self, max_depth: usize) {
+        self.stack.set_max_depth(max_depth);
+    }
+
+    /// Report the program's accesses outside memory and writes below 0x200 as errors, instead of
+    /// wrapping addresses around the end of memory
+    pub fn set_strict_memory(&mut self, strict: bool) {
+        self.memory.set_strict(strict);
+    }
+
+    /// Keep the stack in RAM where the COSMAC VIP does, see `Stack`
+    pub fn set_stack_in_memory(&mut self, in_memory: bool) {
+        self.stack.set_in_memory(in_memory);
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer.count()
+    }
+
+    pub fn set_delay_timer(&mut self, count: u8) {
+        self.delay_timer.set_count(count);
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer.count()
+    }
+
+    pub fn set_sound_timer(&mut self, count: u8) {
+        self.sound_timer.set_count(count);
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// For debuggers poking at memory, writes made here are not seen by watchpoints
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    /// Record reads and writes the program makes inside the watchpoint's range
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// The watched reads and writes made by the last instruction executed
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        &self.watch_hits
+    }
+
+    /// The whole machine as a versioned binary save state, everything but watchpoints and the trace
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.platform.save_state(&mut state);
+        self.quirks.save_state(&mut state);
+        self.memory.save_state(&mut state);
+        self.display.save_state(&mut state);
+        self.stack.save_state(&mut state);
+        self.delay_timer.save_state(&mut state);
+        self.sound_timer.save_state(&mut state);
+        state.u16(self.program_counter as u16);
+        state.u16(self.index_register as u16);
+        state.array(&self.registers);
+        state.array(&self.rpl_flags);
+        state.array(&self.audio_pattern);
+        state.bool(self.audio_pattern_loaded);
+        state.u8(self.pitch);
+        state.u32(self.instructions_per_frame);
+        state.f32(self.instruction_budget);
+        self.timing.save_state(&mut state);
+        state.f32(self.cycle_budget);
+        state.u32(self.frame_cycles);
+        state.bool(self.waiting_for_vblank);
+        state.bool(self.pending_draw.is_some());
+        let (xi, yi, n) = self.pending_draw.unwrap_or((0, 0, 0));
+        state.u8(xi as u8);
+        state.u8(yi as u8);
+        state.u8(n);
+        state.bool(self.waiting_for_key.is_some());
+        let key_wait = self.waiting_for_key.unwrap_or(KeyWait { x: 0, key: None });
+        state.u8(key_wait.x as u8);
+        state.bool(key_wait.key.is_some());
+        state.u8(key_wait.key.unwrap_or(0));
+        state.bool(self.exited);
+        state.u64(self.instructions);
+        self.rng.save_state(&mut state);
+        state.finish()
+    }
+
+    /// A computer exactly as it was when the state was saved, platform and quirks included
+    pub fn from_state(data: &[u8]) -> Result<Self, EmulatorError> {
+        let mut state = StateReader::new(data)?;
+        let platform = Platform::load_state(&mut state)?;
+        let quirks = Quirks::load_state(&mut state)?;
+
+        let mut computer = Self::new(platform, quirks);
+        computer.memory.load_state(&mut state)?;
+        computer.display.load_state(&mut state)?;
+        computer.stack.load_state(&mut state)?;
+        computer.delay_timer.load_state(&mut state)?;
+        computer.sound_timer.load_state(&mut state)?;
+        computer.program_counter = state.u16()? as usize;
+        computer.index_register = state.u16()? as usize;
+        computer.registers.copy_from_slice(state.array(16)?);
+        computer.rpl_flags.copy_from_slice(state.array(16)?);
+        computer.audio_pattern.copy_from_slice(state.array(16)?);
+        computer.audio_pattern_loaded = state.bool()?;
+        computer.pitch = state.u8()?;
+        computer.instructions_per_frame = state.u32()?;
+        computer.instruction_budget = state.f32()?;
+        computer.timing = Timing::load_state(&mut state)?;
+        computer.cycle_budget = state.f32()?;
+        computer.frame_cycles = state.u32()?;
+        computer.waiting_for_vblank = state.bool()?;
+        let pending_draw = state.bool()?;
+        let (xi, yi, n) = (state.u8()? as usize & 0xF, state.u8()? as usize & 0xF, state.u8()? & 0xF);
+        computer.pending_draw = pending_draw.then_some((xi, yi, n));
+        let waiting_for_key = state.bool()?;
+        let x = state.u8()? as usize & 0xF;
+        let pressed = state.bool()?;
+        let key = state.u8()? & 0xF;
+        computer.waiting_for_key = waiting_for_key.then_some(KeyWait { x, key: pressed.then_some(key) });
+        computer.exited = state.bool()?;
+        computer.instructions = state.u64()?;
+        computer.rng = Rng::load_state(&mut state)?;
+        state.finish()?;
+        Ok(computer)
+    }
+
+    /// Replace the machine with a saved one. Watchpoints, the trace, the machine call policy and
+    /// strict memory carry on as they were, and if the state is bad nothing changes
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmulatorError> {
+        let mut loaded = Self::from_state(data)?;
+        loaded.watchpoints = std::mem::take(&mut self.watchpoints);
+        loaded.trace = self.trace.take();
+        loaded.machine_calls = std::mem::replace(&mut self.machine_calls, MachineCallPolicy::Ignore);
+        loaded.memory.set_strict(self.memory.is_strict());
+        *self = loaded;
+        Ok(())
+    }
+
+    /// How many instructions have been executed
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Switch between counting instructions per frame and the COSMAC VIP's machine cycles
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.instruction_budget = 0.0;
+        self.cycle_budget = 0.0;
+        self.frame_cycles = 0;
+    }
+
+    /// How 0NNN machine code calls are handled, they are ignored by default
+    pub fn set_machine_call_policy(&mut self, policy: MachineCallPolicy) {
+        self.machine_calls = policy;
+    }
+
+    /// True while FX0A is waiting for a key to be pressed and released
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key.is_some()
+    }
+
+    /// Restart the CXNN random numbers from seed, the same seed always gives the same numbers
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    pub fn rng_mode(&self) -> RngMode {
+        self.rng.mode()
+    }
+
+    /// Make the CXNN random numbers with mode's algorithm, carrying on from the current seed
+    pub fn set_rng_mode(&mut self, mode: RngMode) {
+        self.rng.set_mode(mode);
+    }
+
+    /// Give `RngMode::Vip` the COSMAC VIP's CHIP-8 interpreter, a dump of its 512 bytes. Its
+    /// random numbers come from the interpreter's own code, which isn't in emulated memory
+    pub fn set_vip_interpreter(&mut self, interpreter: &[u8]) -> Result<(), EmulatorError> {
+        self.rng.set_vip_interpreter(interpreter)
+    }
+
+    /// Write a line to the trace after every instruction, None stops tracing
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// True once a SUPER-CHIP program has executed 00FD
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Emulate one 60Hz frame: the timers tick once and instructions_per_frame instructions are executed
+    pub fn run_frame(&mut self, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        self.update(FRAME_TIME, keypad)
     }
 
-    pub fn update(&mut self, dt: f32, keyboard: &mut Keyboard) {
-        self.delay_timer.update(dt);
+    /// Advance the machine by dt seconds. The timers tick at 60Hz and instructions run at
+    /// instructions_per_frame per 60Hz frame, except that after a draw with the display wait quirk
+    /// nothing more runs until the next vertical blank. With `Timing::CosmacVip` dt is turned
+    /// into machine cycles instead, and the timers tick on the emulated interrupt
+    pub fn update(&mut self, dt: f32, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        self.update_until(dt, keypad, &mut |_| false)?;
+        Ok(())
+    }
+
+    /// Like update, but should_break is asked before each instruction and if it returns true the
+    /// rest of the instructions due are dropped, leaving the program counter at that instruction.
+    /// Returns true if it stopped early
+    pub fn update_until(&mut self, dt: f32, keypad: &dyn Keypad, should_break: &mut dyn FnMut(&Computer) -> bool) -> Result<bool, EmulatorError> {
+        if self.exited {
+            return Ok(false);
+        }
+        if self.timing == Timing::CosmacVip {
+            return self.update_vip(dt, keypad, should_break);
+        }
+
+        let vblank = self.delay_timer.update(dt);
         self.sound_timer.update(dt);
 
-        // fetch instruction
-        let instruction = Instruction::new(self.memory.read_u16(self.program_counter));
-        self.program_counter += 2;
+        if vblank {
+            self.vblank()?;
+        }
+        self.poll_key_wait(keypad);
 
-        // decode & execute
-        let opcode = instruction.op_code();
-        match opcode {
-            0x0 => {
-                let lsb = instruction.n();
-                match lsb {
-                    0x0 => self.op_00e0_clear_screen(instruction),
-                    0xE => self.op_00ee_return_from_subroutine(instruction),
-                    _ => println!("Unknown 0 lsb: {:#06X}", lsb),
-                }
+        self.instruction_budget += dt / FRAME_TIME * self.instructions_per_frame as f32;
+        while self.instruction_budget >= 1.0 {
+            if self.waiting_for_vblank || self.waiting_for_key.is_some() || self.exited {
+                self.instruction_budget = 0.0;
+                break;
             }
-            0x1 => self.op_1nnn_jump(instruction),
-            0x2 => self.op_2nnn_call_subroutine(instruction),
-            0x3 => self.op_3xnn_skip_if_equal(instruction),
-            0x4 => self.op_4xnn_skip_if_not_equal(instruction),
-            0x5 => self.op_5xy0_skip_if_registers_equal(instruction),
-            0x6 => self.op_6xnn_set_register(instruction),
-            0x7 => self.op_7xnn_add_register(instruction),
-            0x8 => {
-                let lsb = instruction.n();
-                match lsb {
-                    0x0 => self.op_8xy0_set(instruction),
-                    0x1 => self.op_8xy1_binary_or(instruction),
-                    0x2 => self.op_8xy2_binary_and(instruction),
-                    0x3 => self.op_8xy3_binary_xor(instruction),
-                    0x4 => self.op_8xy4_add(instruction),
-                    0x5 => self.op_8xy5_subtract(instruction),
-                    0x6 => self.op_8xy6_shift(instruction),
-                    0x7 => self.op_8xy7_subtract(instruction),
-                    0xE => self.op_8xye_shift(instruction),
-                    _ => println!("Unknown 8 lsb: {:#06X}", lsb),
-                }
-            },
-            0x9 => self.op_9xy0_skip_if_registers_not_equal(instruction),
-            0xA => self.op_annn_set_index_register(instruction),
-            0xC => self.op_cxnn_random(instruction),
-            0xD => self.op_dxyn_display(instruction),
-            0xE => {
-                let lsb = instruction.n();
-                match lsb {
-                    0xE => self.op_ex9e_skip_if_key_down(instruction, keyboard),
-                    0x1 => self.op_exa1_skip_if_key_not_down(instruction, keyboard),
-                    _ => println!("Unknown E lsb: {:#06X}", lsb),
-                }
+
+            if should_break(self) {
+                self.instruction_budget = 0.0;
+                return Ok(true);
             }
-            0xF => {
-                let lsb = instruction.nn();
-                match lsb {
-                    0x07 => self.op_fx07_timer(instruction),
-                    0x15 => self.op_fx15_timer(instruction),
-                    0x18 => self.op_fx18_timer(instruction),
-                    0x1E => self.op_fx1e_index_register_add(instruction),
-                    0x0A => self.op_fx0a_get_keyboard_input(instruction),
-                    0x29 => self.op_fx29_font_character(instruction),
-                    0x33 => self.op_fx33_binary_coded_decimal_conversion(instruction),
-                    0x55 => self.op_fx55_store_memory(instruction),
-                    0x65 => self.op_fx65_load_memory(instruction),
-                    _ => println!("Unknown F lsb: {:#06X}", lsb),
+
+            self.instruction_budget -= 1.0;
+            self.step(keypad)?;
+        }
+
+        Ok(false)
+    }
+
+    fn update_vip(&mut self, dt: f32, keypad: &dyn Keypad, should_break: &mut dyn FnMut(&Computer) -> bool) -> Result<bool, EmulatorError> {
+        self.cycle_budget += dt * VIP_MACHINE_CYCLES_PER_SECOND;
+        while self.cycle_budget >= 1.0 {
+            if self.frame_cycles >= VIP_CYCLES_PER_FRAME {
+                self.frame_cycles -= VIP_CYCLES_PER_FRAME;
+                let draw_cycles = self.vip_interrupt(keypad)?;
+                // the interpreter doesn't run while the screen is read out, then draws
+                self.frame_cycles += VIP_DISPLAY_DMA_CYCLES + draw_cycles;
+                self.cycle_budget -= (VIP_DISPLAY_DMA_CYCLES + draw_cycles) as f32;
+                continue;
+            }
+
+            let cycles = if self.waiting_for_vblank || self.waiting_for_key.is_some() || self.exited {
+                // idle until the next interrupt, or for as long as there is time
+                (VIP_CYCLES_PER_FRAME - self.frame_cycles).min(self.cycle_budget as u32)
+            } else {
+                if should_break(self) {
+                    self.cycle_budget = 0.0;
+                    return Ok(true);
                 }
-            },
-            _ => println!("Unknown opcode: {:#06X}", instruction.instruction),
+                self.step(keypad)?;
+                // a slow instruction can overrun the budget, which the next update pays back
+                vip_cycles(self.executing.1, &self.registers)
+            };
+            self.cycle_budget -= cycles as f32;
+            self.frame_cycles += cycles;
         }
+
+        Ok(false)
+    }
+
+    /// The VIP's 60Hz interrupt, the timers count down and a waiting draw can go ahead.
+    /// Returns the machine cycles the draw took
+    fn vip_interrupt(&mut self, keypad: &dyn Keypad) -> Result<u32, EmulatorError> {
+        self.delay_timer.tick();
+        self.sound_timer.tick();
+        self.poll_key_wait(keypad);
+        self.vblank()
     }
 
-    fn op_ex9e_skip_if_key_down(&mut self, instruction: Instruction, keyboard: &mut Keyboard) {
-        let xi = instruction.x();
+    /// The end of a wait for the 60Hz vertical blank, a draw waiting for it is done now.
+    /// If the draw fails the program counter is left pointing at it. Returns the machine cycles
+    /// the draw takes on the VIP
+    fn vblank(&mut self) -> Result<u32, EmulatorError> {
+        self.waiting_for_vblank = false;
+        let Some((xi, yi, n)) = self.pending_draw.take() else {
+            return Ok(0);
+        };
+        let result = self.draw_sprite(xi, yi, n);
+        if result.is_err() {
+            self.program_counter = self.executing.0;
+        }
+        result
+    }
+
+    /// Execute a single instruction without advancing the timers.
+    /// If the instruction fails the program counter is left pointing at it. While FX0A is
+    /// waiting for a key this only checks the keypad, and a draw waiting for the 60Hz interrupt
+    /// is done without waiting
+    pub fn step(&mut self, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        if self.exited {
+            return Ok(());
+        }
+        if self.waiting_for_key.is_some() {
+            self.poll_key_wait(keypad);
+            return Ok(());
+        }
+
+        self.watch_hits.clear();
+        // stepping doesn't wait for the interrupt, a draw waiting for it is done instead
+        if self.pending_draw.is_some() {
+            self.vblank()?;
+            return Ok(());
+        }
+        let address = self.program_counter;
+        let result = self.execute_next_instruction(keypad);
+        if result.is_err() {
+            self.program_counter = address;
+            return result;
+        }
+        // off the end of memory the program counter wraps, unless strict memory reports it on the next fetch
+        self.program_counter = self.memory.program_address(self.program_counter, false).unwrap_or(self.program_counter);
+
+        self.instructions += 1;
+        if let Some(mut trace) = self.trace.take() {
+            let (address, op) = self.executing;
+            let written = trace.record(self, address, op);
+            self.trace = Some(trace);
+            written?;
+        }
+        Ok(())
+    }
+
+    /// Finish FX0A once a key has gone down and come back up, as on the COSMAC VIP
+    fn poll_key_wait(&mut self, keypad: &dyn Keypad) {
+        let Some(wait) = self.waiting_for_key.as_mut() else {
+            return;
+        };
+
+        match wait.key {
+            None => wait.key = (0..16).find(|&key| keypad.is_key_down(key)),
+            Some(key) if !keypad.is_key_down(key) => {
+                self.registers[wait.x] = key;
+                self.waiting_for_key = None;
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn execute_next_instruction(&mut self, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        // fetch instruction, plus the word after it in case it is the 4 byte F000 NNNN
+        let address = self.memory.program_address(self.program_counter, false)?;
+        self.program_counter = address;
+        let instruction = Instruction::new(self.read_program_u16(address)?);
+        let next_word = self.read_program_u16(address + 2).unwrap_or(0);
+
+        // decode & execute
+        let op = Op::decode(instruction, next_word, self.platform);
+        self.program_counter += op.size();
+        self.executing = (address, op);
+
+        match op {
+            Op::ClearScreen => self.op_00e0_clear_screen(),
+            Op::Return => self.op_00ee_return_from_subroutine(),
+            Op::ScrollDown { n } => self.op_00cn_scroll_down(n),
+            Op::ScrollUp { n } => self.op_00dn_scroll_up(n),
+            Op::ScrollRight => self.op_00fb_scroll_right(),
+            Op::ScrollLeft => self.op_00fc_scroll_left(),
+            Op::Exit => self.op_00fd_exit(),
+            Op::Lores => self.op_00fe_lores(),
+            Op::Hires => self.op_00ff_hires(),
+            Op::MachineCall { nnn } => self.op_0nnn_machine_call(nnn, address),
+            Op::Jump { nnn } => self.op_1nnn_jump(nnn),
+            Op::Call { nnn } => self.op_2nnn_call_subroutine(nnn),
+            Op::SkipIfEqual { x, nn } => self.op_3xnn_skip_if_equal(x, nn),
+            Op::SkipIfNotEqual { x, nn } => self.op_4xnn_skip_if_not_equal(x, nn),
+            Op::SkipIfRegistersEqual { x, y } => self.op_5xy0_skip_if_registers_equal(x, y),
+            Op::StoreRegisterRange { x, y } => self.op_5xy2_store_registers(x, y),
+            Op::LoadRegisterRange { x, y } => self.op_5xy3_load_registers(x, y),
+            Op::SetImm { x, nn } => self.op_6xnn_set_register(x, nn),
+            Op::AddImm { x, nn } => self.op_7xnn_add_register(x, nn),
+            Op::Set { x, y } => self.op_8xy0_set(x, y),
+            Op::Or { x, y } => self.op_8xy1_binary_or(x, y),
+            Op::And { x, y } => self.op_8xy2_binary_and(x, y),
+            Op::Xor { x, y } => self.op_8xy3_binary_xor(x, y),
+            Op::Add { x, y } => self.op_8xy4_add(x, y),
+            Op::Sub { x, y } => self.op_8xy5_subtract(x, y),
+            Op::ShiftRight { x, y } => self.op_8xy6_shift(x, y),
+            Op::SubReverse { x, y } => self.op_8xy7_subtract(x, y),
+            Op::ShiftLeft { x, y } => self.op_8xye_shift(x, y),
+            Op::SkipIfRegistersNotEqual { x, y } => self.op_9xy0_skip_if_registers_not_equal(x, y),
+            Op::SetIndex { nnn } => self.op_annn_set_index_register(nnn),
+            Op::JumpWithOffset { nnn } => self.op_bnnn_jump_with_offset(nnn),
+            Op::Random { x, nn } => self.op_cxnn_random(x, nn),
+            Op::Draw { x, y, n } => self.op_dxyn_display(x, y, n),
+            Op::SkipIfKey { x } => self.op_ex9e_skip_if_key_down(x, keypad),
+            Op::SkipIfNotKey { x } => self.op_exa1_skip_if_key_not_down(x, keypad),
+            Op::LongIndex { nnnn } => self.op_f000_long_index(nnnn),
+            Op::SelectPlanes { n } => self.op_fn01_select_planes(n),
+            Op::LoadAudioPattern => self.op_f002_audio_pattern(),
+            Op::GetDelay { x } => self.op_fx07_timer(x),
+            Op::WaitKey { x } => self.op_fx0a_wait_for_key(x),
+            Op::SetDelay { x } => self.op_fx15_timer(x),
+            Op::SetSound { x } => self.op_fx18_timer(x),
+            Op::AddIndex { x } => self.op_fx1e_index_register_add(x),
+            Op::FontCharacter { x } => self.op_fx29_font_character(x),
+            Op::BigFontCharacter { x } => self.op_fx30_big_font_character(x),
+            Op::BinaryCodedDecimal { x } => self.op_fx33_binary_coded_decimal_conversion(x),
+            Op::Pitch { x } => self.op_fx3a_pitch(x),
+            Op::Store { x } => self.op_fx55_store_memory(x),
+            Op::Load { x } => self.op_fx65_load_memory(x),
+            Op::StoreFlags { x } => self.op_fx75_store_flags(x),
+            Op::LoadFlags { x } => self.op_fx85_load_flags(x),
+            Op::Unknown(opcode) => Err(EmulatorError::UnknownOpcode { opcode, address }),
+        }
+    }
+
+    fn op_ex9e_skip_if_key_down(&mut self, xi: usize, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
-        let keycode = KEY_MAP[x as usize];
-        println!("Key down: {:?}", keycode);
-        let is_down = keyboard.get_keystate(keycode).is_down();
+        let is_down = keypad.is_key_down(x);
         if is_down {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
         }
+
+        Ok(())
     }
 
-    fn op_exa1_skip_if_key_not_down(&mut self, instruction: Instruction, keyboard: &mut Keyboard) {
-        let xi = instruction.x();
+    fn op_exa1_skip_if_key_not_down(&mut self, xi: usize, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
-        let keycode = KEY_MAP[x as usize];
-        println!("Key not down: {:?}", keycode);
-        let is_down = keyboard.get_keystate(keycode).is_down();
+        let is_down = keypad.is_key_down(x);
         if !is_down {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
+        }
+
+        Ok(())
+    }
+
+    /// Read memory on behalf of the program, so watchpoints see it
+    fn read_memory(&mut self, address: usize, buffer: &mut [u8]) -> Result<(), EmulatorError> {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_memory_u8(address + i)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_memory_u8(&mut self, address: usize) -> Result<u8, EmulatorError> {
+        let address = self.memory.program_address(address, false)?;
+        let value = self.memory.read_u8(address)?;
+        self.watch(Access::Read, address, value);
+
+        Ok(value)
+    }
+
+    fn write_memory_u8(&mut self, address: usize, value: u8) -> Result<(), EmulatorError> {
+        let address = self.memory.program_address(address, true)?;
+        self.memory.write_u8(address, value)?;
+        self.watch(Access::Write, address, value);
+
+        Ok(())
+    }
+
+    /// An instruction word, which isn't a watched read
+    fn read_program_u16(&self, address: usize) -> Result<u16, EmulatorError> {
+        let high = self.memory.read_u8(self.memory.program_address(address, false)?)?;
+        let low = self.memory.read_u8(self.memory.program_address(address + 1, false)?)?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    fn watch(&mut self, access: Access, address: usize, value: u8) {
+        let (program_counter, op) = self.executing;
+        for watchpoint in &self.watchpoints {
+            if watchpoint.matches(address, access) {
+                self.watch_hits.push(WatchHit { access, address, value, program_counter, op, action: watchpoint.action });
+            }
         }
     }
 
-    fn op_cxnn_random(&mut self, instruction: Instruction) {
-        let mut rng = rand::thread_rng();
-        let xi = instruction.x();
-        let value = instruction.nn();
+    /// Skip over the next instruction, which on XO-CHIP may be the 4 byte F000 NNNN
+    fn skip_next_instruction(&mut self) -> Result<(), EmulatorError> {
+        let next = self.read_program_u16(self.program_counter)?;
+        self.program_counter += 2;
+        if self.platform.has_xo_chip_instructions() && next == 0xF000 {
+            self.program_counter += 2;
+        }
 
-        let rand: u8 = rng.gen();
+        Ok(())
+    }
+
+    fn op_cxnn_random(&mut self, xi: usize, value: u8) -> Result<(), EmulatorError> {
+        let rand = self.rng.next_u8();
         let result = value & rand;
         self.registers[xi] = result;
+
+        Ok(())
     }
 
-    fn op_fx65_load_memory(&mut self, instruction: Instruction) {
-        let x: usize = instruction.x();
+    fn op_fx65_load_memory(&mut self, x: usize) -> Result<(), EmulatorError> {
         for i in 0..=x {
-            let value = self.memory.read_u8(self.index_register + i);
+            let value = self.read_memory_u8(self.index_register + i)?;
             self.registers[i] = value;
         }
+
+        self.index_register += self.quirks.load_store_index_increment.amount(x);
+
+        Ok(())
     }
 
-    fn op_fx55_store_memory(&mut self, instruction: Instruction) {
-        let x: usize = instruction.x();
+    fn op_fx55_store_memory(&mut self, x: usize) -> Result<(), EmulatorError> {
         for i in 0..=x {
             let value = self.registers[i];
-            self.memory.write_u8(self.index_register + i, value);
+            self.write_memory_u8(self.index_register + i, value)?;
         }
+
+        self.index_register += self.quirks.load_store_index_increment.amount(x);
+
+        Ok(())
     }
 
-    fn op_fx33_binary_coded_decimal_conversion(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx33_binary_coded_decimal_conversion(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let mut value = self.registers[xi];
 
         let ones_place = value % 10;
-        self.memory.write_u8(self.index_register + 2, ones_place);
+        self.write_memory_u8(self.index_register + 2, ones_place)?;
         value /= 10;
         
         let tens_place = value % 10;
-        self.memory.write_u8(self.index_register + 1, tens_place);
+        self.write_memory_u8(self.index_register + 1, tens_place)?;
         value /= 10;
 
         let hundreds_place = value % 10;
-        self.memory.write_u8(self.index_register + 0, hundreds_place);
+        self.write_memory_u8(self.index_register, hundreds_place)?;
+
+        Ok(())
     }
 
-    fn op_fx29_font_character(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx29_font_character(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         self.index_register = FONT_MEMORY_START + (5 * x as usize);
+
+        Ok(())
+    }
+
+    /// Nothing more runs until a key is pressed and released, but the timers keep going
+    fn op_fx0a_wait_for_key(&mut self, x: usize) -> Result<(), EmulatorError> {
+        self.waiting_for_key = Some(KeyWait { x, key: None });
+
+        Ok(())
+    }
+
+    fn op_0nnn_machine_call(&mut self, nnn: u16, address: usize) -> Result<(), EmulatorError> {
+        match &mut self.machine_calls {
+            MachineCallPolicy::Ignore => Ok(()),
+            MachineCallPolicy::Error => Err(EmulatorError::MachineCall { nnn, address }),
+            MachineCallPolicy::Hook(_) => {
+                // the hook gets the whole computer, so it is out of its place while it runs
+                let MachineCallPolicy::Hook(mut hook) = std::mem::replace(&mut self.machine_calls, MachineCallPolicy::Ignore) else {
+                    unreachable!()
+                };
+                let result = hook(self, nnn);
+                self.machine_calls = MachineCallPolicy::Hook(hook);
+                result
+            }
+        }
+    }
+
+    fn op_f000_long_index(&mut self, address: u16) -> Result<(), EmulatorError> {
+        self.index_register = address as usize;
+
+        Ok(())
+    }
+
+    fn op_fn01_select_planes(&mut self, planes: u8) -> Result<(), EmulatorError> {
+        self.display.select_planes(planes);
+
+        Ok(())
+    }
+
+    fn op_f002_audio_pattern(&mut self) -> Result<(), EmulatorError> {
+        let mut pattern = [0; 16];
+        self.read_memory(self.index_register, &mut pattern)?;
+        self.audio_pattern = pattern;
+        self.audio_pattern_loaded = true;
+
+        Ok(())
+    }
+
+    fn op_fx3a_pitch(&mut self, xi: usize) -> Result<(), EmulatorError> {
+        self.pitch = self.registers[xi];
+
+        Ok(())
+    }
+
+    fn op_fx30_big_font_character(&mut self, xi: usize) -> Result<(), EmulatorError> {
+        let x = self.registers[xi] & 0xF;
+        self.index_register = BIG_FONT_MEMORY_START + (10 * x as usize);
+
+        Ok(())
+    }
+
+    fn op_fx75_store_flags(&mut self, x: usize) -> Result<(), EmulatorError> {
+        self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+
+        Ok(())
     }
 
-    fn op_fx0a_get_keyboard_input(&mut self, _instruction: Instruction) {
-        println!("todo: op_fx0a_get_keyboard_input")
+    fn op_fx85_load_flags(&mut self, x: usize) -> Result<(), EmulatorError> {
+        self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+
+        Ok(())
     }
 
-    fn op_fx1e_index_register_add(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx1e_index_register_add(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi] as usize;
         self.index_register += x;
+
+        Ok(())
     }
 
-    fn op_fx07_timer(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx07_timer(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let count = self.delay_timer.count();
         self.registers[xi] = count;
+
+        Ok(())
     }
 
-    fn op_fx15_timer(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx15_timer(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         self.delay_timer.set_count(x);
+
+        Ok(())
     }
 
-    fn op_fx18_timer(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_fx18_timer(&mut self, xi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         self.sound_timer.set_count(x);
+
+        Ok(())
     }
 
-    fn op_8xy0_set(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy0_set(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         self.registers[xi] = self.registers[yi];
+
+        Ok(())
     }
 
-    fn op_8xy1_binary_or(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy1_binary_or(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         self.registers[xi] |= self.registers[yi];
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+
+        Ok(())
     }
 
-    fn op_8xy2_binary_and(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy2_binary_and(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         self.registers[xi] &= self.registers[yi];
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+
+        Ok(())
     }
 
-    fn op_8xy3_binary_xor(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy3_binary_xor(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         self.registers[xi] ^= self.registers[yi];
+
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+
+        Ok(())
     }
 
-    fn op_8xy4_add(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy4_add(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         let sum = self.registers[xi] as usize + self.registers[yi] as usize;
         
         if sum > 255 {
@@ -290,10 +938,11 @@ impl Computer {
         }
 
         self.registers[xi] = (sum & 0xFF) as u8;
+
+        Ok(())
     }
 
-    fn op_8xy5_subtract(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy5_subtract(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         let y = self.registers[yi];
         
@@ -304,10 +953,11 @@ impl Computer {
         }
 
         self.registers[xi] = x.wrapping_sub(y);
+
+        Ok(())
     }
 
-    fn op_8xy7_subtract(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_8xy7_subtract(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         let y = self.registers[yi];
 
@@ -318,109 +968,630 @@ impl Computer {
         }
 
         self.registers[xi] = y.wrapping_sub(x);
+
+        Ok(())
     }
 
-    fn op_8xy6_shift(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_8xy6_shift(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
+        if self.quirks.shift_uses_vy {
+            self.registers[xi] = self.registers[yi];
+        }
 
         // Save LSB in VF
-	    self.registers[0xF] = self.registers[xi] & 0x1;
-
+        let lsb = self.registers[xi] & 0x1;
         self.registers[xi] >>= 1;
+        self.registers[0xF] = lsb;
+
+        Ok(())
     }
 
-    fn op_8xye_shift(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
+    fn op_8xye_shift(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
+        if self.quirks.shift_uses_vy {
+            self.registers[xi] = self.registers[yi];
+        }
 
         // Save MSB in VF
-	    self.registers[0xF] = (self.registers[xi] & 0x80) >> 7;
-
+        let msb = (self.registers[xi] & 0x80) >> 7;
         self.registers[xi] <<= 1;
+        self.registers[0xF] = msb;
+
+        Ok(())
     }
 
-    fn op_5xy0_skip_if_registers_equal(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_5xy0_skip_if_registers_equal(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         let y = self.registers[yi];
         if x == y {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
         }
+
+        Ok(())
+    }
+
+    fn op_5xy2_store_registers(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
+        let count = xi.abs_diff(yi);
+        for i in 0..=count {
+            let register = if xi <= yi { xi + i } else { xi - i };
+            self.write_memory_u8(self.index_register + i, self.registers[register])?;
+        }
+
+        Ok(())
     }
 
-    fn op_9xy0_skip_if_registers_not_equal(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
+    fn op_5xy3_load_registers(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
+        let count = xi.abs_diff(yi);
+        for i in 0..=count {
+            let register = if xi <= yi { xi + i } else { xi - i };
+            self.registers[register] = self.read_memory_u8(self.index_register + i)?;
+        }
+
+        Ok(())
+    }
+
+    fn op_9xy0_skip_if_registers_not_equal(&mut self, xi: usize, yi: usize) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         let y = self.registers[yi];
         if x != y {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
         }
+
+        Ok(())
     }
 
-    fn op_3xnn_skip_if_equal(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
-        let value = instruction.nn();
+    fn op_3xnn_skip_if_equal(&mut self, xi: usize, value: u8) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         if x == value {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
         }
+
+        Ok(())
     }
 
-    fn op_4xnn_skip_if_not_equal(&mut self, instruction: Instruction) {
-        let xi = instruction.x();
-        let value = instruction.nn();
+    fn op_4xnn_skip_if_not_equal(&mut self, xi: usize, value: u8) -> Result<(), EmulatorError> {
         let x = self.registers[xi];
         if x != value {
-            self.program_counter += 2;
+            self.skip_next_instruction()?;
         }
+
+        Ok(())
     }
 
-    fn op_2nnn_call_subroutine(&mut self, instruction: Instruction) {
-        self.stack.push(self.program_counter);
-        let address = instruction.nnn();
+    fn op_2nnn_call_subroutine(&mut self, address: u16) -> Result<(), EmulatorError> {
+        let depth = self.stack.depth();
+        self.stack.push(self.program_counter)?;
+        if self.stack.is_in_memory() {
+            let slot = Stack::memory_address(depth);
+            let [high, low] = (self.program_counter as u16).to_be_bytes();
+            self.write_memory_u8(slot, high)?;
+            self.write_memory_u8(slot + 1, low)?;
+        }
         self.program_counter = address as usize;
+
+        Ok(())
     }
 
-    fn op_00ee_return_from_subroutine(&mut self, _instruction: Instruction) {
-        self.program_counter = self.stack.pop() as usize;
+    fn op_00ee_return_from_subroutine(&mut self) -> Result<(), EmulatorError> {
+        let mut address = self.stack.pop()?;
+        // the program may have changed its return address
+        if self.stack.is_in_memory() {
+            let slot = Stack::memory_address(self.stack.depth());
+            address = u16::from_be_bytes([self.read_memory_u8(slot)?, self.read_memory_u8(slot + 1)?]) as usize;
+        }
+        self.program_counter = address;
+
+        Ok(())
     }
 
-    fn op_00e0_clear_screen(&mut self, _instruction: Instruction) {
+    fn op_00e0_clear_screen(&mut self) -> Result<(), EmulatorError> {
         self.display.clear();
+
+        Ok(())
+    }
+
+    /// SUPER-CHIP 1.1 scrolls by hires pixels even in lores, half as many lores pixels rounded down, where
+    /// XO-CHIP scrolls by pixels of the current resolution
+    fn scroll_amount(&self, pixels: usize) -> usize {
+        if self.platform == Platform::Schip && !self.display.is_hires() {
+            pixels / 2
+        } else {
+            pixels
+        }
+    }
+
+    fn op_00cn_scroll_down(&mut self, n: u8) -> Result<(), EmulatorError> {
+        self.display.scroll_down(self.scroll_amount(n as usize));
+
+        Ok(())
+    }
+
+    fn op_00dn_scroll_up(&mut self, n: u8) -> Result<(), EmulatorError> {
+        self.display.scroll_up(self.scroll_amount(n as usize));
+
+        Ok(())
+    }
+
+    fn op_00fb_scroll_right(&mut self) -> Result<(), EmulatorError> {
+        self.display.scroll_right(self.scroll_amount(4));
+
+        Ok(())
     }
 
-    fn op_annn_set_index_register(&mut self, instruction: Instruction) {
-        let value = instruction.nnn();
+    fn op_00fc_scroll_left(&mut self) -> Result<(), EmulatorError> {
+        self.display.scroll_left(self.scroll_amount(4));
+
+        Ok(())
+    }
+
+    fn op_00fd_exit(&mut self) -> Result<(), EmulatorError> {
+        self.exited = true;
+
+        Ok(())
+    }
+
+    fn op_00fe_lores(&mut self) -> Result<(), EmulatorError> {
+        self.display.set_hires(false, self.platform.has_xo_chip_instructions());
+
+        Ok(())
+    }
+
+    fn op_00ff_hires(&mut self) -> Result<(), EmulatorError> {
+        self.display.set_hires(true, self.platform.has_xo_chip_instructions());
+
+        Ok(())
+    }
+
+    fn op_annn_set_index_register(&mut self, value: u16) -> Result<(), EmulatorError> {
         self.index_register = value as usize;
+
+        Ok(())
     }
 
-    fn op_dxyn_display(&mut self, instruction: Instruction) {
-        let [xi, yi] = instruction.xy();
-        let num_rows = instruction.n();
+    fn op_dxyn_display(&mut self, xi: usize, yi: usize, n: u8) -> Result<(), EmulatorError> {
+        // the VIP interpreter waits for the interrupt before drawing
+        if self.timing == Timing::CosmacVip {
+            self.pending_draw = Some((xi, yi, n));
+            self.waiting_for_vblank = true;
+            return Ok(());
+        }
+
+        self.draw_sprite(xi, yi, n)?;
+        if self.quirks.display_wait {
+            self.waiting_for_vblank = true;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the machine cycles the drawing takes on the VIP
+    fn draw_sprite(&mut self, xi: usize, yi: usize, n: u8) -> Result<u32, EmulatorError> {
+        let num_rows = n as usize;
         let x = self.registers[xi];
         let y = self.registers[yi];
-        let vf = self.display.xor_sprite(x, y, num_rows, &self.memory, self.index_register);
+
+        // SUPER-CHIP draws a 16x16 sprite for DXY0
+        let (sprite_width, sprite_rows) = if num_rows == 0 && self.platform.has_schip_instructions() {
+            (16, 16)
+        } else {
+            (8, num_rows)
+        };
+        let mut sprite = vec![0; sprite_rows * sprite_width / 8 * self.display.selected_plane_count()];
+        self.read_memory(self.index_register, &mut sprite)?;
+
+        let (collided_rows, clipped_rows) = self.display.xor_sprite(x, y, &sprite, sprite_width, self.quirks.clip_sprites);
+        let vf = if self.platform == Platform::Schip && self.display.is_hires() {
+            collided_rows + clipped_rows
+        } else {
+            (collided_rows > 0) as u8
+        };
         self.registers[0xF] = vf;
+
+        let drawn_rows = (sprite_rows - clipped_rows as usize) as u32;
+        let wrapped_rows = if (x as usize & (self.display.width() - 1)) + sprite_width > self.display.width() { drawn_rows } else { 0 };
+        Ok(vip_draw_cycles(x, drawn_rows, collided_rows as u32, wrapped_rows))
     }
 
-    fn op_1nnn_jump(&mut self, instruction: Instruction) {
-        let address = instruction.nnn();
+    fn op_1nnn_jump(&mut self, address: u16) -> Result<(), EmulatorError> {
         self.program_counter = address as usize;
+
+        Ok(())
     }
 
-    fn op_6xnn_set_register(&mut self, instruction: Instruction) {
-        let register = instruction.x();
-        let value = instruction.nn();
-        self.registers[register as usize] = value;
+    fn op_bnnn_jump_with_offset(&mut self, address: u16) -> Result<(), EmulatorError> {
+        let address = address as usize;
+        let offset = if self.quirks.jump_with_offset_uses_vx {
+            self.registers[address >> 8]
+        } else {
+            self.registers[0x0]
+        };
+        self.program_counter = address + offset as usize;
+
+        Ok(())
+    }
+
+    fn op_6xnn_set_register(&mut self, register: usize, value: u8) -> Result<(), EmulatorError> {
+        self.registers[register] = value;
+
+        Ok(())
+    }
+
+    fn op_7xnn_add_register(&mut self, register: usize, value: u8) -> Result<(), EmulatorError> {
+        self.registers[register] = self.registers[register].wrapping_add(value);
+
+        Ok(())
+    }
+
+    pub fn draw(&mut self, screen: &mut dyn FrameSink) {
+        self.display.draw(screen);
     }
 
-    fn op_7xnn_add_register(&mut self, instruction: Instruction) {
-        let register = instruction.x();
-        let value = instruction.nn();
-        self.registers[register as usize] = self.registers[register as usize].wrapping_add(value);
+    /// What the buzzer should play: nothing unless the sound timer is running, and the audio
+    /// pattern once an XO-CHIP program has loaded one
+    pub fn tone(&self) -> Option<Tone> {
+        if self.sound_timer.count() == 0 {
+            return None;
+        }
+
+        if self.audio_pattern_loaded {
+            Some(Tone::Pattern { pattern: self.audio_pattern, pitch: self.pitch })
+        } else {
+            Some(Tone::Beep)
+        }
     }
 
-    pub fn draw(&mut self, sdl: &mut SdlSystem) {
-        self.display.draw(sdl);
+    pub fn play_audio(&self, audio: &mut dyn AudioOutput) {
+        audio.play(self.tone());
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Headless, IndexIncrement, SCHIP_STACK_DEPTH, VIP_STACK_ADDRESS, VIP_STACK_DEPTH};
+
+    /// A computer on platform with its usual quirks that has run the first steps instructions of rom
+    fn run(platform: Platform, rom: &[u8], steps: usize) -> Computer {
+        run_with_quirks(platform, platform.default_quirks(), rom, steps)
+    }
+
+    fn run_with_quirks(platform: Platform, quirks: Quirks, rom: &[u8], steps: usize) -> Computer {
+        let mut computer = Computer::new(platform, quirks);
+        computer.load_program(rom).unwrap();
+        for _ in 0..steps {
+            computer.step(&Headless::new()).unwrap();
+        }
+        computer
+    }
+
+    fn pixel(computer: &Computer, x: usize, y: usize) -> u8 {
+        let display = computer.display();
+        display.pixels()[y * display.width() + x]
+    }
+
+    #[test]
+    fn xo_chip_stores_and_loads_register_ranges() {
+        // v1..v3 = 1, 2, 3, I = 0x300, save v1 - v3, load v3 - v1
+        let rom = [0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0x53, 0x13];
+        let computer = run(Platform::XoChip, &rom, 5);
+        let mut saved = [0; 3];
+        computer.memory().read_u8_array(0x300, &mut saved).unwrap();
+        assert_eq!(saved, [1, 2, 3]);
+        assert_eq!(computer.index_register(), 0x300);
+
+        let computer = run(Platform::XoChip, &rom, 6);
+        assert_eq!(computer.registers()[1..4], [3, 2, 1]);
+    }
+
+    #[test]
+    fn xo_chip_long_index_skips_its_address() {
+        let computer = run(Platform::XoChip, &[0xF0, 0x00, 0x12, 0x34], 1);
+        assert_eq!(computer.index_register(), 0x1234);
+        assert_eq!(computer.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn xo_chip_draws_each_selected_plane() {
+        // plane 3, I = 0x208, draw one row at 0, 0 from 0x80 for plane 1 and 0x40 for plane 2
+        let rom = [0xF3, 0x01, 0xA2, 0x08, 0xD0, 0x01, 0x12, 0x06, 0x80, 0x40];
+        let computer = run(Platform::XoChip, &rom, 3);
+        assert_eq!((pixel(&computer, 0, 0), pixel(&computer, 1, 0)), (1, 2));
+
+        // plane 2 alone, clear
+        let rom = [0xF3, 0x01, 0xA2, 0x0C, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0, 0x12, 0x0A, 0x80, 0x40];
+        let computer = run(Platform::XoChip, &rom, 5);
+        assert_eq!((pixel(&computer, 0, 0), pixel(&computer, 1, 0)), (1, 0));
+    }
+
+    #[test]
+    fn xo_chip_plays_the_audio_pattern_at_the_pitch() {
+        // I = 0x208, audio, v0 = 0x70, pitch := v0, then the pattern
+        let mut rom = vec![0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        let pattern: [u8; 16] = std::array::from_fn(|i| i as u8 * 0x11);
+        rom.extend(pattern);
+        let mut computer = run(Platform::XoChip, &rom, 4);
+        assert_eq!(computer.tone(), None);
+        computer.set_sound_timer(5);
+        assert_eq!(computer.tone(), Some(Tone::Pattern { pattern, pitch: 0x70 }));
+    }
+
+    #[test]
+    fn changing_resolution_clears_the_screen_only_on_xo_chip() {
+        // I = the 0 font character, draw its top row in lores, then hires
+        let rom = [0xA0, 0x50, 0xD0, 0x01, 0x00, 0xFF];
+        let computer = run(Platform::XoChip, &rom, 3);
+        assert!(computer.display().pixels().iter().all(|&pixel| pixel == 0));
+
+        // SUPER-CHIP doubles the pixel to 2x2, and halves it again going back
+        let computer = run(Platform::Schip, &rom, 3);
+        assert_eq!([0, 1].map(|y| [pixel(&computer, 0, y), pixel(&computer, 1, y)]), [[1, 1], [1, 1]]);
+        assert_eq!((pixel(&computer, 7, 0), pixel(&computer, 8, 0)), (1, 0));
+        let computer = run(Platform::Schip, &[0xA0, 0x50, 0xD0, 0x01, 0x00, 0xFF, 0x00, 0xFE], 4);
+        assert_eq!((pixel(&computer, 0, 0), pixel(&computer, 1, 0), pixel(&computer, 0, 1)), (1, 1, 0));
+    }
+
+    #[test]
+    fn super_chip_scrolls_lores_by_half() {
+        // the top row of the 0 font character in lores, scroll down 4, scroll right
+        let rom = [0xA0, 0x50, 0xD0, 0x01, 0x00, 0xC4, 0x00, 0xFB];
+        let computer = run(Platform::Schip, &rom, 4);
+        assert_eq!((pixel(&computer, 2, 2), pixel(&computer, 1, 2), pixel(&computer, 2, 1)), (1, 0, 0));
+        let computer = run(Platform::XoChip, &rom, 4);
+        assert_eq!((pixel(&computer, 4, 4), pixel(&computer, 3, 4), pixel(&computer, 4, 3)), (1, 0, 0));
+    }
+
+    #[test]
+    fn super_chip_scrolls_hires() {
+        // hires, the top row of the 0 font character, scroll down 3, right 4, left 4, left 4
+        let rom = [0x00, 0xFF, 0xA0, 0x50, 0xD0, 0x01, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC];
+        let computer = run(Platform::Schip, &rom, 4);
+        assert_eq!((pixel(&computer, 0, 3), pixel(&computer, 0, 0)), (1, 0));
+        let computer = run(Platform::Schip, &rom, 5);
+        assert_eq!((pixel(&computer, 3, 3), pixel(&computer, 4, 3), pixel(&computer, 7, 3), pixel(&computer, 8, 3)), (0, 1, 1, 0));
+        let computer = run(Platform::Schip, &rom, 7);
+        // the left half of the row went off the edge
+        assert_eq!(computer.display().pixels().iter().filter(|&&pixel| pixel != 0).count(), 0);
+    }
+
+    #[test]
+    fn super_chip_points_at_the_big_font() {
+        let computer = run(Platform::Schip, &[0x60, 0x07, 0xF0, 0x30], 2);
+        assert_eq!(computer.index_register(), BIG_FONT_MEMORY_START + 70);
+        let mut character = [0; 10];
+        computer.memory().read_u8_array(computer.index_register(), &mut character).unwrap();
+        assert_eq!(character, BIG_FONT[70..80]);
+    }
+
+    #[test]
+    fn super_chip_saves_and_loads_flags() {
+        // v0, v1 = 0x42, 0x17, save flags v1, zero them, load flags v0
+        let rom = [0x60, 0x42, 0x61, 0x17, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF0, 0x85];
+        let computer = run(Platform::Schip, &rom, 6);
+        assert_eq!(computer.registers()[..2], [0x42, 0x00]);
+    }
+
+    #[test]
+    fn super_chip_draws_16x16_sprites() {
+        // hires, I = a sprite with only its top row lit, draw it 16x16 twice at 120, 56, which
+        // clips 8 columns and 8 rows
+        let rom = [0x00, 0xFF, 0x60, 0x78, 0x61, 0x38, 0xA2, 0x0E, 0xD0, 0x10, 0xD0, 0x10, 0x12, 0x0C, 0xFF, 0xFF];
+        let computer = run(Platform::Schip, &rom, 5);
+        assert_eq!((pixel(&computer, 120, 56), pixel(&computer, 127, 56), pixel(&computer, 120, 57)), (1, 1, 0));
+        // hires VF counts the clipped rows, and on the second pass the row that collided
+        assert_eq!(computer.registers()[0xF], 8);
+        let computer = run(Platform::Schip, &rom, 6);
+        assert_eq!(computer.registers()[0xF], 9);
+
+        // CHIP-8 has no 16x16 sprites, DXY0 draws nothing
+        let computer = run(Platform::Chip8, &[0xA2, 0x04, 0xD0, 0x10, 0xFF, 0xFF], 2);
+        assert!(computer.display().pixels().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn super_chip_exits() {
+        let mut computer = run(Platform::Schip, &[0x00, 0xFD, 0x60, 0x01], 1);
+        computer.step(&Headless::new()).unwrap();
+        assert!(computer.has_exited());
+        assert_eq!((computer.program_counter(), computer.registers()[0]), (0x202, 0));
+    }
+
+    /// A CHIP-8 computer with the VIP quirks changed by quirk that has run steps instructions of rom
+    fn run_with_quirk(quirk: impl Fn(&mut Quirks), rom: &[u8], steps: usize) -> Computer {
+        let mut quirks = Quirks::cosmac_vip();
+        quirk(&mut quirks);
+        run_with_quirks(Platform::Chip8, quirks, rom, steps)
+    }
+
+    #[test]
+    fn shift_quirk_picks_the_source() {
+        // v0 = 5, v1 = 6, v0 >>= v1
+        let rom = [0x60, 0x05, 0x61, 0x06, 0x80, 0x16];
+        let computer = run_with_quirk(|quirks| quirks.shift_uses_vy = true, &rom, 3);
+        assert_eq!((computer.registers()[0], computer.registers()[0xF]), (3, 0));
+        let computer = run_with_quirk(|quirks| quirks.shift_uses_vy = false, &rom, 3);
+        assert_eq!((computer.registers()[0], computer.registers()[0xF]), (2, 1));
+    }
+
+    #[test]
+    fn index_increment_quirk_moves_i() {
+        // I = 0x300, save v2, load v2
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+        for (increment, moved) in [(IndexIncrement::XPlusOne, 3), (IndexIncrement::X, 2), (IndexIncrement::None, 0)] {
+            let computer = run_with_quirk(|quirks| quirks.load_store_index_increment = increment, &rom, 2);
+            assert_eq!(computer.index_register(), 0x300 + moved);
+            let computer = run_with_quirk(|quirks| quirks.load_store_index_increment = increment, &rom, 3);
+            assert_eq!(computer.index_register(), 0x300 + 2 * moved);
+        }
+    }
+
+    #[test]
+    fn vf_reset_quirk_clears_vf_after_logic() {
+        // vf = 5, v0 |= v1
+        let rom = [0x6F, 0x05, 0x80, 0x11];
+        assert_eq!(run_with_quirk(|quirks| quirks.vf_reset = true, &rom, 2).registers()[0xF], 0);
+        assert_eq!(run_with_quirk(|quirks| quirks.vf_reset = false, &rom, 2).registers()[0xF], 5);
+    }
+
+    #[test]
+    fn clip_quirk_stops_sprites_at_the_edge() {
+        // the top row of the 0 font character at 62, 0, half of it past the right edge
+        let rom = [0x60, 0x3E, 0xA0, 0x50, 0xD0, 0x11];
+        let computer = run_with_quirk(|quirks| quirks.clip_sprites = true, &rom, 3);
+        assert_eq!((pixel(&computer, 63, 0), pixel(&computer, 0, 0)), (1, 0));
+        let computer = run_with_quirk(|quirks| quirks.clip_sprites = false, &rom, 3);
+        assert_eq!((pixel(&computer, 63, 0), pixel(&computer, 0, 0), pixel(&computer, 1, 0)), (1, 1, 1));
+    }
+
+    #[test]
+    fn jump_quirk_picks_the_offset_register() {
+        // v0 = 5, v2 = 3, jump0 0x210
+        let rom = [0x60, 0x05, 0x62, 0x03, 0xB2, 0x10];
+        assert_eq!(run_with_quirk(|quirks| quirks.jump_with_offset_uses_vx = true, &rom, 3).program_counter(), 0x213);
+        assert_eq!(run_with_quirk(|quirks| quirks.jump_with_offset_uses_vx = false, &rom, 3).program_counter(), 0x215);
+    }
+
+    #[test]
+    fn display_wait_quirk_waits_for_the_next_frame() {
+        // draw, then count instructions in v1
+        let rom = [0xD0, 0x01, 0x71, 0x01, 0x12, 0x02];
+        let mut computer = run_with_quirk(|quirks| quirks.display_wait = true, &rom, 0);
+        computer.run_frame(&Headless::new()).unwrap();
+        assert_eq!(computer.registers()[1], 0);
+        let mut computer = run_with_quirk(|quirks| quirks.display_wait = false, &rom, 0);
+        computer.run_frame(&Headless::new()).unwrap();
+        assert!(computer.registers()[1] > 0);
+    }
+
+    #[test]
+    fn presets_match_their_interpreters() {
+        assert_eq!(Platform::Chip8.default_quirks(), Quirks::cosmac_vip());
+        assert_eq!(Quirks::chip_48().load_store_index_increment, IndexIncrement::X);
+        assert_eq!(Quirks::super_chip().load_store_index_increment, IndexIncrement::None);
+        assert!(Quirks::cosmac_vip().vf_reset && !Quirks::super_chip().vf_reset && !Quirks::xo_chip().vf_reset);
+        assert!(!Quirks::xo_chip().clip_sprites && Quirks::super_chip().jump_with_offset_uses_vx);
+    }
+
+    #[test]
+    fn key_wait_finishes_when_the_key_is_released() {
+        // v3 := key, v4 := 1
+        let mut computer = run(Platform::Chip8, &[0xF3, 0x0A, 0x64, 0x01], 1);
+        let mut keypad = Headless::new();
+        computer.step(&keypad).unwrap();
+        assert!(computer.is_waiting_for_key());
+
+        keypad.set_key(0xB, true);
+        for _ in 0..3 {
+            computer.step(&keypad).unwrap();
+        }
+        // still down, the wait goes on
+        assert!(computer.is_waiting_for_key());
+        assert_eq!(computer.registers()[3], 0);
+
+        keypad.set_key(0xB, false);
+        computer.step(&keypad).unwrap();
+        assert!(!computer.is_waiting_for_key());
+        assert_eq!(computer.registers()[3], 0xB);
+        computer.step(&keypad).unwrap();
+        assert_eq!((computer.registers()[4], computer.program_counter()), (1, 0x204));
+    }
+
+    #[test]
+    fn machine_calls_follow_the_policy() {
+        let rom = [0x01, 0x23, 0x60, 0x01];
+        let computer = run(Platform::Chip8, &rom, 2);
+        assert_eq!(computer.registers()[0], 1);
+
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        computer.set_machine_call_policy(MachineCallPolicy::Error);
+        let error = computer.step(&Headless::new()).unwrap_err();
+        assert!(matches!(error, EmulatorError::MachineCall { nnn: 0x123, address: 0x200 }));
+        assert_eq!(computer.program_counter(), 0x200);
+
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        computer.set_machine_call_policy(MachineCallPolicy::Hook(Box::new(|computer, nnn| {
+            computer.set_register(0xA, nnn as u8);
+            Ok(())
+        })));
+        computer.step(&Headless::new()).unwrap();
+        assert_eq!((computer.registers()[0xA], computer.program_counter()), (0x23, 0x202));
+    }
+
+    /// Run steps instructions, then the error from the next one
+    fn run_to_error(computer: &mut Computer, steps: usize) -> EmulatorError {
+        for _ in 0..steps {
+            computer.step(&Headless::new()).unwrap();
+        }
+        computer.step(&Headless::new()).unwrap_err()
+    }
+
+    #[test]
+    fn stack_overflows_past_the_platform_depth() {
+        // a subroutine that calls itself
+        let rom = [0x22, 0x00];
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        assert!(matches!(run_to_error(&mut computer, VIP_STACK_DEPTH), EmulatorError::StackOverflow));
+        assert_eq!((computer.stack().depth(), computer.program_counter()), (VIP_STACK_DEPTH, 0x200));
+
+        let mut computer = run(Platform::Schip, &rom, 0);
+        assert!(matches!(run_to_error(&mut computer, SCHIP_STACK_DEPTH), EmulatorError::StackOverflow));
+
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        computer.set_stack_depth(2);
+        assert!(matches!(run_to_error(&mut computer, 2), EmulatorError::StackOverflow));
+
+        let mut computer = run(Platform::Chip8, &[0x00, 0xEE], 0);
+        assert!(matches!(run_to_error(&mut computer, 0), EmulatorError::StackUnderflow));
+    }
+
+    #[test]
+    fn stack_in_memory_returns_to_the_address_in_ram() {
+        // call 0x206, v0 := 1, loop, and at 0x206 return
+        let rom = [0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x00, 0xEE];
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        computer.set_stack_in_memory(true);
+        computer.step(&Headless::new()).unwrap();
+        let mut slot = [0; 2];
+        computer.memory().read_u8_array(VIP_STACK_ADDRESS + 0x2E, &mut slot).unwrap();
+        assert_eq!(slot, [0x02, 0x02]);
+        assert_eq!(Stack::memory_address(0), VIP_STACK_ADDRESS + 0x2E);
+
+        // the program's return address is changed to skip v0 := 1
+        computer.memory_mut().load(Stack::memory_address(0), &[0x02, 0x04]).unwrap();
+        computer.step(&Headless::new()).unwrap();
+        assert_eq!((computer.program_counter(), computer.stack().depth()), (0x204, 0));
+
+        // only 24 return addresses fit in the VIP's stack area
+        computer.set_stack_depth(100);
+        assert_eq!(computer.stack().max_depth(), 24);
+    }
+
+    #[test]
+    fn program_memory_accesses_wrap_unless_strict() {
+        // v0, v1 = 0x11, 0x22, I = 0xFFF, save v1
+        let rom = [0x60, 0x11, 0x61, 0x22, 0xAF, 0xFF, 0xF1, 0x55];
+        let computer = run(Platform::Chip8, &rom, 4);
+        assert_eq!(computer.memory().read_u8(0xFFF).unwrap(), 0x11);
+        assert_eq!(computer.memory().read_u8(0x000).unwrap(), 0x22);
+
+        let mut computer = run(Platform::Chip8, &rom, 0);
+        computer.set_strict_memory(true);
+        assert!(matches!(run_to_error(&mut computer, 3), EmulatorError::MemoryOutOfRange { address: 0x1000 }));
+        assert_eq!(computer.program_counter(), 0x206);
+
+        // I = 0x100, save v0
+        let mut computer = run(Platform::Chip8, &[0xA1, 0x00, 0xF0, 0x55], 0);
+        computer.set_strict_memory(true);
+        assert!(matches!(run_to_error(&mut computer, 1), EmulatorError::ProtectedWrite { address: 0x100 }));
+    }
+
+    #[test]
+    fn program_counter_wraps_unless_strict() {
+        let mut computer = run(Platform::Chip8, &[0x1F, 0xFE], 1);
+        computer.step(&Headless::new()).unwrap();
+        assert_eq!(computer.program_counter(), 0x000);
+
+        let mut computer = run(Platform::Chip8, &[0x1F, 0xFE], 1);
+        computer.set_strict_memory(true);
+        computer.step(&Headless::new()).unwrap();
+        assert!(matches!(run_to_error(&mut computer, 0), EmulatorError::MemoryOutOfRange { .. }));
+    }
+}
@@ -0,0 +1,812 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::computer::Computer;
+use crate::debugger::{Breakpoint, Debugger, StopReason};
+use crate::disassembler::{label, Disassembler, Syntax};
+use crate::expression::Expression;
+use crate::frontend::Keypad;
+use crate::instruction::Instruction;
+use crate::opcode::Op;
+use crate::platform::Platform;
+use crate::quirks::{Quirks, QuirksPreset};
+use crate::timing::Timing;
+
+/// The only thread, CHIP-8 has one CPU
+const THREAD_ID: i64 = 1;
+/// The disassembly listing is the one source the adapter serves
+const LISTING_REFERENCE: i64 = 1;
+/// The one variables scope
+const REGISTERS_REFERENCE: i64 = 1;
+/// Longest message read, far more than any request needs. A Content-Length past this is a
+/// broken or hostile client, not something to allocate for
+const MAX_MESSAGE_LENGTH: usize = 1 << 20;
+
+/// What the client asked to debug, from the arguments of its launch request
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaunchArguments {
+    pub program: PathBuf,
+    pub platform: Platform,
+    pub quirks: Option<QuirksPreset>,
+    pub instructions_per_frame: Option<u32>,
+    pub timing: Timing,
+    /// The language of the listing breakpoints are set in
+    pub syntax: Syntax,
+    pub stop_on_entry: bool,
+}
+
+/// A breakpoint as the client set it, kept so a request replacing one kind leaves the other alone
+struct ClientBreakpoint {
+    id: i64,
+    breakpoint: Breakpoint,
+}
+
+/// A Debug Adapter Protocol server, so VS Code and other DAP clients can debug a ROM.
+///
+/// Messages are read on their own thread and handled once per frame, like `GdbServer`. The
+/// ROM is shown to the client as a disassembly listing in the launch syntax, so breakpoints are
+/// set on listing lines, or on instruction addresses from the disassembly view.
+pub struct DapServer {
+    messages: Receiver<Value>,
+    output: Box<dyn Write + Send>,
+    sequence: i64,
+    debugger: Debugger,
+    launch: Option<LaunchArguments>,
+    rom: Vec<u8>,
+    lines: Vec<(Option<usize>, String)>,
+    /// Index into lines of the instruction or data at each address
+    line_of_address: HashMap<usize, usize>,
+    lines_start_at_1: bool,
+    source_breakpoints: Vec<ClientBreakpoint>,
+    instruction_breakpoints: Vec<ClientBreakpoint>,
+    /// Ids of the debugger's breakpoints, in the same order
+    breakpoint_ids: Vec<i64>,
+    next_breakpoint_id: i64,
+}
+
+impl DapServer {
+    pub fn new(input: impl Read + Send + 'static, output: impl Write + Send + 'static) -> Self {
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            messages,
+            output: Box::new(output),
+            sequence: 1,
+            debugger: Debugger::new(),
+            launch: None,
+            rom: vec![],
+            lines: vec![],
+            line_of_address: HashMap::new(),
+            lines_start_at_1: true,
+            source_breakpoints: vec![],
+            instruction_breakpoints: vec![],
+            breakpoint_ids: vec![],
+            next_breakpoint_id: 1,
+        }
+    }
+
+    /// Talk to the client over stdin and stdout, the usual way a DAP client starts an adapter
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout())
+    }
+
+    pub fn launch_arguments(&self) -> Option<&LaunchArguments> {
+        self.launch.as_ref()
+    }
+
+    /// Answer requests until the client launches a ROM that loads, and return the computer
+    /// running it. Returns None if the client disconnects first
+    pub fn launch(&mut self) -> Option<Computer> {
+        while let Ok(message) = self.messages.recv() {
+            let command = message["command"].as_str().unwrap_or_default().to_string();
+            match command.as_str() {
+                "initialize" => {
+                    self.lines_start_at_1 = message["arguments"]["linesStartAt1"].as_bool().unwrap_or(true);
+                    self.respond(&message, Ok(capabilities()));
+                }
+                "launch" => match self.load(&message["arguments"]) {
+                    Ok(computer) => {
+                        self.respond(&message, Ok(Value::Null));
+                        self.send_event("initialized", Value::Null);
+                        return Some(computer);
+                    }
+                    Err(error) => self.respond(&message, Err(error)),
+                },
+                "disconnect" | "terminate" => {
+                    self.respond(&message, Ok(Value::Null));
+                    return None;
+                }
+                _ => self.respond(&message, Err(format!("{} before launch", command))),
+            }
+        }
+        None
+    }
+
+    /// Handle whatever the client sent, then emulate one 60Hz frame unless paused.
+    /// Returns false once the client disconnects or the program exits
+    pub fn run_frame(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> bool {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => {
+                    if !self.handle(&message, computer, keypad) {
+                        return false;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+
+        let result = self.debugger.run_frame(computer, keypad);
+        for hit in self.debugger.take_watch_log() {
+            self.send_event("output", json!({ "category": "console", "output": format!("Watch: {}\n", hit) }));
+        }
+        match result {
+            Ok(Some(StopReason::Breakpoint(i))) => {
+                let id = self.breakpoint_ids.get(i).copied();
+                self.send_stopped("breakpoint", None, id);
+            }
+            Ok(Some(StopReason::Watchpoint(hit))) => self.send_stopped("data breakpoint", Some(hit.to_string()), None),
+            Ok(Some(_)) => self.send_stopped("step", None, None),
+            Ok(None) => {}
+            Err(error) => self.send_stopped("exception", Some(error.to_string()), None),
+        }
+
+        if computer.has_exited() {
+            self.send_event("exited", json!({ "exitCode": 0 }));
+            self.send_event("terminated", Value::Null);
+            return false;
+        }
+        true
+    }
+
+    fn load(&mut self, arguments: &Value) -> Result<Computer, String> {
+        let launch = parse_launch_arguments(arguments)?;
+        let rom = std::fs::read(&launch.program)
+            .map_err(|error| format!("Could not load {}: {}", launch.program.display(), error))?;
+
+        let quirks = match launch.quirks {
+            Some(preset) => Quirks::from_preset(preset),
+            None => launch.platform.default_quirks(),
+        };
+        let mut computer = Computer::new(launch.platform, quirks);
+        if let Some(instructions_per_frame) = launch.instructions_per_frame {
+            computer.set_instructions_per_frame(instructions_per_frame);
+        }
+        computer.set_timing(launch.timing);
+        computer.load_program(&rom)
+            .map_err(|error| format!("Could not load {}: {}", launch.program.display(), error))?;
+
+        self.lines = Disassembler::new(&rom, launch.platform).lines(launch.syntax);
+        self.line_of_address = self.lines.iter()
+            .enumerate()
+            .filter_map(|(line, (address, _))| Some(((*address)?, line)))
+            .collect();
+        self.rom = rom;
+        self.launch = Some(launch);
+        Ok(computer)
+    }
+
+    /// Returns false when the session is over
+    fn handle(&mut self, message: &Value, computer: &mut Computer, keypad: &dyn Keypad) -> bool {
+        let arguments = &message["arguments"];
+        let command = message["command"].as_str().unwrap_or_default();
+        let response = match command {
+            "setBreakpoints" => Ok(self.set_breakpoints(arguments)),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(arguments)),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => {
+                self.respond(message, Ok(Value::Null));
+                if self.launch.as_ref().is_some_and(|launch| launch.stop_on_entry) {
+                    self.send_stopped("entry", None, None);
+                } else {
+                    self.debugger.resume();
+                }
+                return true;
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(computer)),
+            "scopes" => Ok(json!({ "scopes": [{
+                "name": "Registers",
+                "presentationHint": "registers",
+                "variablesReference": REGISTERS_REFERENCE,
+                "expensive": false,
+            }] })),
+            "variables" => Ok(json!({ "variables": registers(computer) })),
+            "setVariable" => set_variable(computer, arguments),
+            "source" => Ok(json!({ "content": self.listing(), "mimeType": "text/x-asm" })),
+            "continue" => {
+                self.debugger.resume();
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" => {
+                let result = if command == "next" {
+                    self.debugger.step_over(computer, keypad)
+                } else {
+                    self.debugger.step(computer, keypad)
+                };
+                self.respond(message, Ok(Value::Null));
+                match result {
+                    Err(error) => self.send_stopped("exception", Some(error.to_string()), None),
+                    // stepping over a call stops later, from run_frame
+                    Ok(()) if self.debugger.is_paused() => self.send_stopped("step", None, None),
+                    Ok(()) => {}
+                }
+                return true;
+            }
+            "stepOut" => {
+                if self.debugger.run_to_return(computer) {
+                    Ok(Value::Null)
+                } else {
+                    Err("Not in a subroutine".into())
+                }
+            }
+            "pause" => {
+                self.debugger.pause();
+                self.respond(message, Ok(Value::Null));
+                self.send_stopped("pause", None, None);
+                return true;
+            }
+            "evaluate" => evaluate(computer, arguments),
+            "readMemory" => read_memory(computer, arguments),
+            "writeMemory" => write_memory(computer, arguments),
+            "disassemble" => self.disassemble(computer, arguments),
+            "disconnect" => {
+                self.respond(message, Ok(Value::Null));
+                return false;
+            }
+            "terminate" => {
+                self.respond(message, Ok(Value::Null));
+                self.send_event("terminated", Value::Null);
+                return false;
+            }
+            _ => Err(format!("Unsupported request {}", command)),
+        };
+
+        self.respond(message, response);
+        true
+    }
+
+    /// Replaces every breakpoint in the listing, lines are mapped to the address on them or,
+    /// for a label, the address after it. There is no source map from other files, such as the
+    /// .8o source the ROM was assembled from, so breakpoints in them are refused with the reason
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let source = &arguments["source"];
+        let in_listing = source["sourceReference"].as_i64() == Some(LISTING_REFERENCE);
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+
+        self.source_breakpoints.clear();
+        let mut replies = vec![];
+        for request in &requested {
+            let line = request["line"].as_i64().unwrap_or(0);
+            if !in_listing {
+                let file = source["path"].as_str().or(source["name"].as_str()).unwrap_or("This file");
+                let message = format!("{} isn't mapped to the ROM, set breakpoints in the listing {} instead", file, self.source_name());
+                replies.push(json!({ "verified": false, "line": line, "message": message }));
+                continue;
+            }
+            let address = self.line_index(line).and_then(|index| self.lines[index..].iter().find_map(|(address, _)| *address));
+            let Some(address) = address else {
+                replies.push(json!({ "verified": false, "line": line, "message": "Not a line of the ROM listing" }));
+                continue;
+            };
+
+            match client_breakpoint(Breakpoint::at(address), request) {
+                Ok(breakpoint) => {
+                    let id = self.next_id();
+                    let line = self.client_line(self.line_of_address[&address]);
+                    replies.push(json!({ "id": id, "verified": true, "line": line, "source": self.source(), "instructionReference": reference(address) }));
+                    self.source_breakpoints.push(ClientBreakpoint { id, breakpoint });
+                }
+                Err(error) => replies.push(json!({ "verified": false, "line": line, "message": error })),
+            }
+        }
+
+        self.update_breakpoints();
+        json!({ "breakpoints": replies })
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Value {
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+
+        self.instruction_breakpoints.clear();
+        let mut replies = vec![];
+        for request in &requested {
+            let address = request["instructionReference"].as_str()
+                .and_then(parse_reference)
+                .and_then(|address| address.checked_add_signed(request["offset"].as_i64().unwrap_or(0) as isize));
+            let Some(address) = address else {
+                replies.push(json!({ "verified": false, "message": "Bad instruction reference" }));
+                continue;
+            };
+
+            match client_breakpoint(Breakpoint::at(address), request) {
+                Ok(breakpoint) => {
+                    let id = self.next_id();
+                    replies.push(json!({ "id": id, "verified": true, "instructionReference": reference(address) }));
+                    self.instruction_breakpoints.push(ClientBreakpoint { id, breakpoint });
+                }
+                Err(error) => replies.push(json!({ "verified": false, "message": error })),
+            }
+        }
+
+        self.update_breakpoints();
+        json!({ "breakpoints": replies })
+    }
+
+    /// Give the debugger both kinds of breakpoint again, hit counts start over
+    fn update_breakpoints(&mut self) {
+        self.debugger.clear_breakpoints();
+        self.breakpoint_ids.clear();
+        for client in self.source_breakpoints.iter().chain(&self.instruction_breakpoints) {
+            self.debugger.add_breakpoint(client.breakpoint.clone());
+            self.breakpoint_ids.push(client.id);
+        }
+    }
+
+    /// The innermost frame is at the program counter, each one out from it is at the call
+    /// that the stack's return address comes back to. Frames are named after the subroutine
+    /// they are in, found from the call into it
+    fn stack_trace(&self, computer: &Computer) -> Value {
+        let mut calls = computer.stack().frames().map(|(call, _)| call);
+
+        let mut frames = vec![];
+        let mut address = computer.program_counter();
+        loop {
+            let caller = calls.next();
+            let name = match caller.map(|call| op_at(computer, call)) {
+                Some(Op::Call { nnn }) => label(nnn as usize),
+                Some(_) => "subroutine".into(),
+                None => "main".into(),
+            };
+
+            let mut frame = json!({
+                "id": frames.len(),
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": reference(address),
+            });
+            if let Some(&line) = self.line_of_address.get(&address) {
+                frame["source"] = self.source();
+                frame["line"] = json!(self.client_line(line));
+                frame["column"] = json!(1);
+            }
+            frames.push(frame);
+
+            match caller {
+                Some(call) => address = call,
+                None => break,
+            }
+        }
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    /// Instructions decoded from memory as it is now, so code the program wrote shows up.
+    /// Addresses outside memory are padded with invalid entries, as the client asked for a count
+    fn disassemble(&self, computer: &Computer, arguments: &Value) -> Result<Value, String> {
+        let start = arguments["memoryReference"].as_str().and_then(parse_reference).ok_or("Bad memory reference")? as i64
+            + arguments["offset"].as_i64().unwrap_or(0)
+            + arguments["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        let count = arguments["instructionCount"].as_u64().unwrap_or(0) as usize;
+        let launch = self.launch.as_ref().ok_or("Nothing launched")?;
+        let disassembler = Disassembler::new(&self.rom, launch.platform);
+
+        let mut instructions = vec![];
+        let mut address = start;
+        while instructions.len() < count {
+            let word = usize::try_from(address).ok().and_then(|address| computer.memory().read_u16(address).ok());
+            let Some(word) = word else {
+                instructions.push(json!({ "address": format!("{:#05X}", address.max(0)), "instruction": "", "presentationHint": "invalid" }));
+                address += 2;
+                continue;
+            };
+
+            let address_usize = address as usize;
+            let op = op_at(computer, address_usize);
+            let bytes = if op.size() == 4 {
+                format!("{:04X}{:04X}", word, computer.memory().read_u16(address_usize + 2).unwrap_or(0))
+            } else {
+                format!("{:04X}", word)
+            };
+            let mut instruction = json!({
+                "address": reference(address_usize),
+                "instructionBytes": bytes,
+                "instruction": disassembler.format(op, launch.syntax),
+            });
+            if let Some(&line) = self.line_of_address.get(&address_usize) {
+                instruction["location"] = self.source();
+                instruction["line"] = json!(self.client_line(line));
+            }
+            instructions.push(instruction);
+            address += op.size() as i64;
+        }
+
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn listing(&self) -> String {
+        self.lines.iter().map(|(_, line)| format!("{}\n", line)).collect()
+    }
+
+    fn source_name(&self) -> String {
+        let Some(launch) = self.launch.as_ref() else {
+            return String::new();
+        };
+        let stem = launch.program.file_stem().unwrap_or_default().to_string_lossy();
+        match launch.syntax {
+            Syntax::Classic => format!("{}.asm", stem),
+            Syntax::Octo => format!("{}.8o", stem),
+        }
+    }
+
+    fn source(&self) -> Value {
+        json!({ "name": self.source_name(), "sourceReference": LISTING_REFERENCE })
+    }
+
+    fn line_index(&self, client_line: i64) -> Option<usize> {
+        let index = client_line - self.lines_start_at_1 as i64;
+        usize::try_from(index).ok().filter(|&index| index < self.lines.len())
+    }
+
+    fn client_line(&self, index: usize) -> usize {
+        index + self.lines_start_at_1 as usize
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.next_breakpoint_id += 1;
+        self.next_breakpoint_id - 1
+    }
+
+    fn send_stopped(&mut self, reason: &str, text: Option<String>, breakpoint_id: Option<i64>) {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        if let Some(id) = breakpoint_id {
+            body["hitBreakpointIds"] = json!([id]);
+        }
+        self.send_event("stopped", body);
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message);
+    }
+
+    /// A client that went away shows up as the input closing, so write errors are ignored here
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.sequence);
+        self.sequence += 1;
+        let content = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", content.len(), content).ok();
+        self.output.flush().ok();
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsHitConditionalBreakpoints": true,
+        "supportsEvaluateForHovers": true,
+        "supportsSetVariable": true,
+        "supportsReadMemoryRequest": true,
+        "supportsWriteMemoryRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsSteppingGranularity": false,
+        "supportsTerminateRequest": true,
+    })
+}
+
+fn parse_launch_arguments(arguments: &Value) -> Result<LaunchArguments, String> {
+    let program = arguments["program"].as_str().ok_or("launch needs a program, the path of the ROM")?;
+    let platform = match arguments["platform"].as_str() {
+        Some(name) => Platform::from_name(name).ok_or_else(|| format!("Unknown platform {}", name))?,
+        None => Platform::Chip8,
+    };
+    let quirks = match arguments["quirks"].as_str() {
+        Some(name) => Some(QuirksPreset::from_name(name).ok_or_else(|| format!("Unknown quirks {}", name))?),
+        None => None,
+    };
+    let timing = match arguments["timing"].as_str() {
+        Some(name) => Timing::from_name(name).ok_or_else(|| format!("Unknown timing {}", name))?,
+        None => Timing::Instructions,
+    };
+    let syntax = match arguments["syntax"].as_str() {
+        Some(name) => Syntax::from_name(name).ok_or_else(|| format!("Unknown syntax {}", name))?,
+        None => Syntax::Octo,
+    };
+
+    Ok(LaunchArguments {
+        program: PathBuf::from(program),
+        platform,
+        quirks,
+        instructions_per_frame: arguments["instructionsPerFrame"].as_u64().map(|count| count as u32),
+        timing,
+        syntax,
+        stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Add the condition and hit count of a source or instruction breakpoint. A hit condition of N
+/// pauses on the Nth hit
+fn client_breakpoint(mut breakpoint: Breakpoint, request: &Value) -> Result<Breakpoint, String> {
+    if let Some(condition) = request["condition"].as_str().filter(|condition| !condition.trim().is_empty()) {
+        breakpoint.condition = Some(Expression::parse(condition).map_err(|error| error.to_string())?);
+    }
+    if let Some(hits) = request["hitCondition"].as_str().filter(|hits| !hits.trim().is_empty()) {
+        let hits: u32 = hits.trim().parse().map_err(|_| format!("Hit condition {} is not a count", hits))?;
+        breakpoint.ignore_count = hits.saturating_sub(1);
+    }
+    Ok(breakpoint)
+}
+
+/// V0 to VF, then I, PC, SP, DT and ST. I and PC link to the memory view
+fn registers(computer: &Computer) -> Vec<Value> {
+    let mut variables: Vec<Value> = computer.registers()
+        .iter()
+        .enumerate()
+        .map(|(x, value)| json!({ "name": format!("V{:X}", x), "value": format!("{:#04X}", value), "variablesReference": 0 }))
+        .collect();
+
+    let pointers = [("I", computer.index_register()), ("PC", computer.program_counter())];
+    for (name, value) in pointers {
+        variables.push(json!({ "name": name, "value": format!("{:#05X}", value), "variablesReference": 0, "memoryReference": reference(value) }));
+    }
+    let counts = [("SP", computer.stack().depth()), ("DT", computer.delay_timer() as usize), ("ST", computer.sound_timer() as usize)];
+    for (name, value) in counts {
+        variables.push(json!({ "name": name, "value": value.to_string(), "variablesReference": 0 }));
+    }
+    variables
+}
+
+/// The new value is an expression, so V3 can be set to `V4 + 1`. The stack depth can't be
+/// set, it only changes by calling and returning
+fn set_variable(computer: &mut Computer, arguments: &Value) -> Result<Value, String> {
+    let name = arguments["name"].as_str().unwrap_or_default();
+    let expression = Expression::parse(arguments["value"].as_str().unwrap_or_default()).map_err(|error| error.to_string())?;
+    let value = expression.evaluate(computer);
+
+    match name {
+        "I" => computer.set_index_register(value as usize),
+        "PC" => computer.set_program_counter(value as usize),
+        "DT" => computer.set_delay_timer(value as u8),
+        "ST" => computer.set_sound_timer(value as u8),
+        _ => match name.strip_prefix('V').and_then(|digit| usize::from_str_radix(digit, 16).ok()).filter(|&x| x < 16) {
+            Some(x) => computer.set_register(x, value as u8),
+            None => return Err(format!("{} can't be set", name)),
+        },
+    }
+
+    let variable = registers(computer).into_iter().find(|variable| variable["name"] == name).unwrap_or_default();
+    Ok(json!({ "value": variable["value"] }))
+}
+
+/// Expressions use the breakpoint condition language, e.g. `V3 + 1` or `mem[I]`
+fn evaluate(computer: &Computer, arguments: &Value) -> Result<Value, String> {
+    let source = arguments["expression"].as_str().unwrap_or_default();
+    let expression = Expression::parse(source).map_err(|error| error.to_string())?;
+    let value = expression.evaluate(computer);
+    Ok(json!({ "result": format!("{} ({:#X})", value, value), "variablesReference": 0 }))
+}
+
+/// Bytes outside memory are reported as unreadable rather than failing the request, so
+/// nothing past the end of memory is allocated however many the client asks for
+fn read_memory(computer: &Computer, arguments: &Value) -> Result<Value, String> {
+    let start = memory_address(arguments)?;
+    let count = usize::try_from(arguments["count"].as_u64().unwrap_or(0)).unwrap_or(usize::MAX);
+    let readable = computer.memory().size().saturating_sub(start).min(count);
+
+    let mut bytes = vec![0; readable];
+    if readable > 0 {
+        computer.memory().read_u8_array(start, &mut bytes).map_err(|error| error.to_string())?;
+    }
+    Ok(json!({ "address": reference(start), "data": encode_base64(&bytes), "unreadableBytes": count - bytes.len() }))
+}
+
+fn write_memory(computer: &mut Computer, arguments: &Value) -> Result<Value, String> {
+    let start = memory_address(arguments)?;
+    let bytes = decode_base64(arguments["data"].as_str().unwrap_or_default()).ok_or("Bad base64 data")?;
+    computer.memory_mut().load(start, &bytes).map_err(|error| error.to_string())?;
+    Ok(json!({ "bytesWritten": bytes.len() }))
+}
+
+/// memoryReference plus offset
+fn memory_address(arguments: &Value) -> Result<usize, String> {
+    let reference = arguments["memoryReference"].as_str().and_then(parse_reference).ok_or("Bad memory reference")?;
+    reference.checked_add_signed(arguments["offset"].as_i64().unwrap_or(0) as isize).ok_or_else(|| "Bad memory offset".into())
+}
+
+fn op_at(computer: &Computer, address: usize) -> Op {
+    let memory = computer.memory();
+    let instruction = Instruction::new(memory.read_u16(address).unwrap_or(0));
+    let next_word = memory.read_u16(address + 2).unwrap_or(0);
+    Op::decode(instruction, next_word, computer.platform())
+}
+
+/// Memory and instruction references are addresses in hex
+fn reference(address: usize) -> String {
+    format!("{:#05X}", address)
+}
+
+fn parse_reference(text: &str) -> Option<usize> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// Read one message, a Content-Length header then that many bytes of JSON. None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too long", length)));
+    }
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(io::Error::from)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (i, &byte)| word | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(word >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u32> = text.trim_end_matches('=')
+        .bytes()
+        .map(|c| BASE64.iter().position(|&digit| digit == c).map(|digit| digit as u32))
+        .collect::<Option<_>>()?;
+
+    let mut bytes = vec![];
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let word = chunk.iter().enumerate().fold(0u32, |word, (i, &digit)| word | digit << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            bytes.push((word >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server with the ROM launched, and the computer running it
+    fn launched(name: &str, rom: &[u8]) -> (DapServer, Computer) {
+        let path = std::env::temp_dir().join(format!("rust-chip-8-dap-{}-{}.ch8", name, std::process::id()));
+        std::fs::write(&path, rom).unwrap();
+        let mut dap = DapServer::new(io::empty(), io::sink());
+        let computer = dap.load(&json!({ "program": path, "syntax": "classic" })).unwrap();
+        std::fs::remove_file(&path).ok();
+        (dap, computer)
+    }
+
+    #[test]
+    fn sets_breakpoints_on_listing_lines() {
+        let (mut dap, _) = launched("listing", &[0x60, 0x01, 0x12, 0x00]);
+        let reply = dap.set_breakpoints(&json!({
+            "source": { "name": dap.source_name(), "sourceReference": LISTING_REFERENCE },
+            "breakpoints": [{ "line": 3 }],
+        }));
+
+        // line 1 is the label the jump goes to
+        assert_eq!(reply["breakpoints"][0]["verified"], true);
+        assert_eq!(reply["breakpoints"][0]["instructionReference"], "0x202");
+        assert_eq!(dap.debugger.breakpoints()[0].address, Some(0x202));
+    }
+
+    #[test]
+    fn refuses_breakpoints_in_other_sources() {
+        let (mut dap, _) = launched("source", &[0x60, 0x01, 0x12, 0x00]);
+        // the Octo source the ROM came from, even with the listing's name, has no map to addresses
+        let reply = dap.set_breakpoints(&json!({
+            "source": { "name": dap.source_name(), "path": "/home/octo/game.8o" },
+            "breakpoints": [{ "line": 1 }],
+        }));
+
+        let breakpoint = &reply["breakpoints"][0];
+        assert_eq!(breakpoint["verified"], false);
+        assert!(breakpoint["message"].as_str().unwrap().starts_with("/home/octo/game.8o isn't mapped to the ROM"));
+        assert!(dap.debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn reads_memory_up_to_the_end() {
+        let (_, computer) = launched("memory", &[0x60, 0x01]);
+        let reply = read_memory(&computer, &json!({ "memoryReference": "0x200", "count": u64::MAX })).unwrap();
+
+        assert_eq!(decode_base64(reply["data"].as_str().unwrap()).unwrap().len(), 0xE00);
+        assert_eq!(reply["unreadableBytes"], usize::MAX - 0xE00);
+    }
+
+    #[test]
+    fn reads_messages() {
+        let body = r#"{"command":"initialize"}"#;
+        let mut input = io::Cursor::new(format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "command": "initialize" })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn refuses_messages_too_long_to_be_real() {
+        let mut input = io::Cursor::new("Content-Length: 18446744073709551615\r\n\r\n{}");
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn launch_names_match_the_command_line() {
+        use clap::ValueEnum;
+
+        assert!(QuirksPreset::ALL.iter().all(|preset| preset.to_possible_value().unwrap().get_name() == preset.name()));
+        assert!(Syntax::ALL.iter().all(|syntax| syntax.to_possible_value().unwrap().get_name() == syntax.name()));
+    }
+}
@@ -0,0 +1,245 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use rust_chip_8::{describe_state, Breakpoint, Computer, Debugger, Keypad, StopReason, WatchAction, Watchpoint};
+
+const HELP: &str = "\
+Commands:
+  c, continue         run until a breakpoint
+  p, pause            pause
+  s, step             execute one instruction
+  n, next             step, running over a 2NNN call
+  f, finish           run until the current subroutine returns
+  b, break [ADDR] [if CONDITION] [after N]
+                      add a breakpoint, e.g. b 0x2A4, b if mem[0x2F0] != 0
+                      or b 0x2A4 if V3 == 0x10 && I > 0x300 after 2
+  d, delete N         remove breakpoint N, as numbered by list
+  l, list             list breakpoints and watchpoints
+  w, watch RANGE [rwx] [log]
+                      watch memory, e.g. w 0x300 or w 0x300-0x302 rw log
+                      (default writes only, and pause when hit)
+  u, unwatch N        remove watchpoint N, as numbered by list
+  r, regs             show registers, stack and timers
+  bt, backtrace       show the subroutine calls in progress
+  q, quit             quit the emulator
+  h, help             show this help";
+
+/// A debugger driven by commands typed into the terminal. Lines are read on their own thread so
+/// the window keeps rendering while waiting for input.
+pub struct DebugConsole {
+    debugger: Debugger,
+    lines: Receiver<String>,
+}
+
+impl DebugConsole {
+    /// Start the console, debugger decides whether the program starts paused
+    pub fn new(debugger: Debugger) -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        if debugger.is_paused() {
+            println!("Debugger started paused, type h for help");
+        }
+        Self {
+            debugger,
+            lines,
+        }
+    }
+
+    pub fn show_state(&self, computer: &Computer) {
+        println!("{}", describe_state(computer));
+        prompt();
+    }
+
+    /// Handle any commands typed since the last frame, then emulate the frame unless paused.
+    /// Returns false once the user quits
+    pub fn run_frame(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    if !self.execute(line.trim(), computer, keypad) {
+                        return false;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // stdin was closed, keep running without a console
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+
+        let result = self.debugger.run_frame(computer, keypad);
+        self.print_watch_log();
+        match result {
+            Ok(Some(reason)) => {
+                match reason {
+                    StopReason::Breakpoint(i) => println!("Breakpoint {}: {}", i, self.debugger.breakpoints()[i]),
+                    StopReason::SteppedOver => println!("Stepped over"),
+                    StopReason::Returned => println!("Returned"),
+                    StopReason::Watchpoint(hit) => println!("Watchpoint: {}", hit),
+                }
+                self.show_state(computer);
+            }
+            Ok(None) => {}
+            Err(error) => {
+                println!("{} (PC {:#05X}), paused", error, computer.program_counter());
+                self.show_state(computer);
+            }
+        }
+
+        true
+    }
+
+    fn execute(&mut self, line: &str, computer: &mut Computer, keypad: &dyn Keypad) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = words.first().copied().unwrap_or("");
+        let argument = words.get(1).copied();
+
+        match command {
+            "" => {}
+            "c" | "continue" => {
+                self.debugger.resume();
+                return true;
+            }
+            "p" | "pause" => {
+                self.debugger.pause();
+                self.show_state(computer);
+                return true;
+            }
+            "s" | "step" => {
+                if let Err(error) = self.debugger.step(computer, keypad) {
+                    println!("{}", error);
+                }
+                self.print_watch_log();
+                self.show_state(computer);
+                return true;
+            }
+            "n" | "next" => {
+                if let Err(error) = self.debugger.step_over(computer, keypad) {
+                    println!("{}", error);
+                }
+                self.print_watch_log();
+                if self.debugger.is_paused() {
+                    self.show_state(computer);
+                }
+                return true;
+            }
+            "f" | "finish" => {
+                if !self.debugger.run_to_return(computer) {
+                    println!("Not in a subroutine");
+                }
+                return true;
+            }
+            "b" | "break" => {
+                let spec = line[command.len()..].trim();
+                match Breakpoint::parse(spec) {
+                    Ok(breakpoint) => {
+                        println!("Breakpoint {}: {}", self.debugger.breakpoints().len(), breakpoint);
+                        self.debugger.add_breakpoint(breakpoint);
+                    }
+                    Err(error) => println!("{}, usage: b [ADDR] [if CONDITION] [after N]", error),
+                }
+            }
+            "d" | "delete" => match argument.and_then(|index| index.parse().ok()).and_then(|index| self.debugger.remove_breakpoint(index)) {
+                Some(_) => {}
+                None => println!("Usage: d N, with N from list"),
+            },
+            "l" | "list" => {
+                for (i, breakpoint) in self.debugger.breakpoints().iter().enumerate() {
+                    println!("Breakpoint {}: {} ({} hits)", i, breakpoint, breakpoint.hits());
+                }
+                for (i, watchpoint) in computer.watchpoints().iter().enumerate() {
+                    println!("Watchpoint {}: {}", i, describe_watchpoint(watchpoint));
+                }
+            }
+            "w" | "watch" => match parse_watchpoint(&words[1..]) {
+                Some(watchpoint) => {
+                    println!("Watchpoint {}: {}", computer.watchpoints().len(), describe_watchpoint(&watchpoint));
+                    computer.add_watchpoint(watchpoint);
+                }
+                None => println!("Usage: w ADDR[-END] [rwx] [log]"),
+            },
+            "u" | "unwatch" => match argument.and_then(|index| index.parse().ok()).and_then(|index| computer.remove_watchpoint(index)) {
+                Some(_) => {}
+                None => println!("Usage: u N, with N from list"),
+            },
+            "r" | "regs" => {
+                self.show_state(computer);
+                return true;
+            }
+            "bt" | "backtrace" => {
+                let stack = computer.stack();
+                println!("{} of {} calls deep", stack.depth(), stack.max_depth());
+                for (i, (call, return_address)) in stack.frames().enumerate() {
+                    println!("#{} call at {:#05X}, returns to {:#05X}", i, call, return_address);
+                }
+            }
+            "q" | "quit" => return false,
+            "h" | "help" => println!("{}", HELP),
+            _ => println!("Unknown command {}, type h for help", command),
+        }
+
+        prompt();
+        true
+    }
+
+    fn print_watch_log(&mut self) {
+        for hit in self.debugger.take_watch_log() {
+            println!("Watch: {}", hit);
+        }
+    }
+}
+
+/// ADDR or START-END, then optionally which accesses to watch and log to carry on instead of pausing
+fn parse_watchpoint(words: &[&str]) -> Option<Watchpoint> {
+    let (range, options) = words.split_first()?;
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(range)?, parse_address(range)?),
+    };
+
+    let mut watchpoint = Watchpoint::new(start, end, WatchAction::Break);
+    for option in options {
+        match *option {
+            "log" => watchpoint.action = WatchAction::Log,
+            accesses if accesses.chars().all(|c| "rwx".contains(c)) => {
+                watchpoint.read = accesses.contains('r');
+                watchpoint.write = accesses.contains('w');
+                watchpoint.execute = accesses.contains('x');
+            }
+            _ => return None,
+        }
+    }
+
+    (start <= end).then_some(watchpoint)
+}
+
+fn describe_watchpoint(watchpoint: &Watchpoint) -> String {
+    let accesses: String = [(watchpoint.read, 'r'), (watchpoint.write, 'w'), (watchpoint.execute, 'x')]
+        .iter()
+        .filter_map(|&(watched, c)| watched.then_some(c))
+        .collect();
+    let action = match watchpoint.action {
+        WatchAction::Break => "break",
+        WatchAction::Log => "log",
+    };
+    format!("{:#05X}-{:#05X} {} {}", watchpoint.start, watchpoint.end, accesses, action)
+}
+
+/// Addresses are hex, with or without 0x
+fn parse_address(text: &str) -> Option<usize> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+fn prompt() {
+    print!("(debug) ");
+    io::stdout().flush().ok();
+}
@@ -0,0 +1,361 @@
+use std::fmt::{self, Write};
+
+use crate::computer::{Computer, FRAME_TIME};
+use crate::error::EmulatorError;
+use crate::expression::{Expression, ParseError};
+use crate::frontend::Keypad;
+use crate::instruction::Instruction;
+use crate::opcode::Op;
+use crate::watchpoint::{Access, WatchAction, WatchHit};
+
+/// Why the debugger paused the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// A breakpoint was hit, the index into `Debugger::breakpoints`
+    Breakpoint(usize),
+    /// A step over a subroutine call came back from the call
+    SteppedOver,
+    /// The current subroutine returned
+    Returned,
+    /// A watchpoint with `WatchAction::Break` was hit
+    Watchpoint(WatchHit),
+}
+
+/// Pauses the program when it reaches an address, when a condition holds, or both.
+/// Without an address the condition is checked before every instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: Option<usize>,
+    pub condition: Option<Expression>,
+    /// How many hits to let pass before pausing
+    pub ignore_count: u32,
+    hits: u32,
+}
+
+impl Breakpoint {
+    pub fn at(address: usize) -> Self {
+        Self { address: Some(address), condition: None, ignore_count: 0, hits: 0 }
+    }
+
+    pub fn when(condition: Expression) -> Self {
+        Self { address: None, condition: Some(condition), ignore_count: 0, hits: 0 }
+    }
+
+    /// Parse `[ADDRESS] [if CONDITION] [after N]`, e.g. `0x2A4 if V3 == 0x10 after 2`.
+    /// The address is hex, with or without 0x, and after N lets the first N hits pass
+    pub fn parse(spec: &str) -> Result<Self, ParseError> {
+        let mut rest = spec.trim();
+        // where rest starts in spec, for error positions
+        let mut start = spec.len() - spec.trim_start().len();
+        let mut breakpoint = Self { address: None, condition: None, ignore_count: 0, hits: 0 };
+
+        if let Some((before, count)) = rest.rsplit_once("after") {
+            if let Ok(count) = count.trim().parse() {
+                breakpoint.ignore_count = count;
+                rest = before.trim_end();
+            }
+        }
+
+        let (first, _) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if first != "if" && !first.is_empty() {
+            let digits = first.strip_prefix("0x").or_else(|| first.strip_prefix("0X")).unwrap_or(first);
+            let address = usize::from_str_radix(digits, 16)
+                .map_err(|_| ParseError { position: 0, message: format!("bad address {}", first) })?;
+            breakpoint.address = Some(address);
+            let after_address = rest[first.len()..].trim_start();
+            start += rest.len() - after_address.len();
+            rest = after_address;
+        }
+
+        // if must be a word of its own, so ifV0 isn't read as if V0
+        if let Some(condition) = rest.strip_prefix("if").filter(|condition| condition.is_empty() || condition.starts_with(char::is_whitespace)) {
+            let offset = start + "if".len();
+            let condition = Expression::parse(condition)
+                .map_err(|error| ParseError { position: error.position + offset, ..error })?;
+            breakpoint.condition = Some(condition);
+        } else if !rest.is_empty() {
+            return Err(ParseError { position: start, message: "expected if".into() });
+        }
+
+        if breakpoint.address.is_none() && breakpoint.condition.is_none() {
+            return Err(ParseError { position: 0, message: "expected an address or a condition".into() });
+        }
+        Ok(breakpoint)
+    }
+
+    /// How many times the breakpoint has been reached with its condition true
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// Count a hit if the program is at the address and the condition holds, true if it should pause
+    fn check(&mut self, computer: &Computer) -> bool {
+        if self.address.is_some_and(|address| address != computer.program_counter()) {
+            return false;
+        }
+        if self.condition.as_ref().is_some_and(|condition| !condition.is_true(computer)) {
+            return false;
+        }
+
+        self.hits += 1;
+        self.hits > self.ignore_count
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(address) = self.address {
+            parts.push(format!("{:#05X}", address));
+        }
+        if let Some(condition) = &self.condition {
+            parts.push(format!("if {}", condition));
+        }
+        if self.ignore_count > 0 {
+            parts.push(format!("after {}", self.ignore_count));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Runs the program until the subroutine depth drops to or below depth
+#[derive(Clone, Copy, Debug)]
+struct RunUntilDepth {
+    depth: usize,
+    reason: StopReason,
+}
+
+/// Pause, step and breakpoint control wrapped around `Computer::update`.
+/// The program starts paused. While paused neither the timers nor the CPU advance, so the
+/// frontend can keep drawing the frozen screen.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    paused: bool,
+    until: Option<RunUntilDepth>,
+    /// Don't stop for the instruction we are resuming from, or we would never get past a breakpoint
+    resuming: bool,
+    watch_log: Vec<WatchHit>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: vec![],
+            paused: true,
+            until: None,
+            resuming: false,
+            watch_log: vec![],
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.until = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    /// Returns the breakpoint's index
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<Breakpoint> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Watch hits with `WatchAction::Log`, and every hit made while single stepping, since the last call
+    pub fn take_watch_log(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_log)
+    }
+
+    /// Execute exactly one instruction, the timers don't advance
+    pub fn step(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        self.pause();
+        if let Some(hit) = execute_hit(computer) {
+            self.watch_log.push(hit);
+        }
+        let result = computer.step(keypad);
+        self.watch_log.extend_from_slice(computer.watch_hits());
+        result
+    }
+
+    /// Like step, but a 2NNN call runs at full speed until it returns
+    pub fn step_over(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        if let Op::Call { .. } = current_op(computer) {
+            self.resume();
+            self.until = Some(RunUntilDepth { depth: computer.stack().depth(), reason: StopReason::SteppedOver });
+            Ok(())
+        } else {
+            self.step(computer, keypad)
+        }
+    }
+
+    /// Run until the current subroutine returns. Returns false if the program is not in a subroutine
+    pub fn run_to_return(&mut self, computer: &Computer) -> bool {
+        let depth = computer.stack().depth();
+        if depth == 0 {
+            return false;
+        }
+
+        self.resume();
+        self.until = Some(RunUntilDepth { depth: depth - 1, reason: StopReason::Returned });
+        true
+    }
+
+    /// Emulate one 60Hz frame unless paused. Returns why it stopped if it paused during the frame
+    pub fn run_frame(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> Result<Option<StopReason>, EmulatorError> {
+        if self.paused {
+            return Ok(None);
+        }
+
+        let mut reason = None;
+        let result = computer.update_until(FRAME_TIME, keypad, &mut |computer| {
+            reason = self.check(computer);
+            reason.is_some()
+        });
+        if result.is_err() {
+            self.pause();
+        }
+        result?;
+
+        if reason.is_some() {
+            self.pause();
+        }
+        Ok(reason)
+    }
+
+    fn check(&mut self, computer: &Computer) -> Option<StopReason> {
+        if self.resuming {
+            self.resuming = false;
+            return None;
+        }
+
+        // hits from the instruction just executed, then an execute hit on the one about to run
+        let mut reason = None;
+        for &hit in computer.watch_hits().iter().chain(execute_hit(computer).iter()) {
+            match hit.action {
+                WatchAction::Log => self.watch_log.push(hit),
+                WatchAction::Break => {
+                    reason = reason.or(Some(StopReason::Watchpoint(hit)));
+                }
+            }
+        }
+        if reason.is_some() {
+            return reason;
+        }
+
+        // every breakpoint counts its hit, even when an earlier one already pauses
+        let mut hit = None;
+        for (i, breakpoint) in self.breakpoints.iter_mut().enumerate() {
+            if breakpoint.check(computer) {
+                hit = hit.or(Some(i));
+            }
+        }
+        if let Some(i) = hit {
+            return Some(StopReason::Breakpoint(i));
+        }
+
+        match self.until {
+            Some(until) if computer.stack().depth() <= until.depth => Some(until.reason),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The instruction at the program counter
+pub fn current_op(computer: &Computer) -> Op {
+    let memory = computer.memory();
+    let address = computer.program_counter();
+    let instruction = Instruction::new(memory.read_u16(address).unwrap_or(0));
+    let next_word = memory.read_u16(address + 2).unwrap_or(0);
+    Op::decode(instruction, next_word, computer.platform())
+}
+
+/// A hit if an execute watchpoint covers the instruction at the program counter
+fn execute_hit(computer: &Computer) -> Option<WatchHit> {
+    let address = computer.program_counter();
+    let watchpoint = computer.watchpoints().iter().find(|watchpoint| watchpoint.matches(address, Access::Execute))?;
+    Some(WatchHit {
+        access: Access::Execute,
+        address,
+        value: computer.memory().read_u8(address).unwrap_or(0),
+        program_counter: address,
+        op: current_op(computer),
+        action: watchpoint.action,
+    })
+}
+
+/// A few lines showing the registers, stack, timers and the next instruction
+pub fn describe_state(computer: &Computer) -> String {
+    let mut state = String::new();
+
+    for (i, value) in computer.registers().iter().enumerate() {
+        let separator = if i % 8 == 7 { "\n" } else { "  " };
+        write!(state, "V{:X}={:02X}{}", i, value, separator).unwrap();
+    }
+
+    let op = current_op(computer);
+    let raw: String = op.encode().iter().map(|byte| format!("{:02X}", byte)).collect();
+    writeln!(state, "PC={:#05X}  I={:#05X}  DT={:02X}  ST={:02X}", computer.program_counter(), computer.index_register(), computer.delay_timer(), computer.sound_timer()).unwrap();
+
+    let stack: Vec<String> = computer.stack().entries().iter().map(|address| format!("{:#05X}", address)).collect();
+    writeln!(state, "Stack: [{}]", stack.join(", ")).unwrap();
+    write!(state, "Next: {} {:?}", raw, op).unwrap();
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn condition_error_position_ignores_after() {
+        let spec = "0x200 if V0 == after 3";
+        let error = Breakpoint::parse(spec).unwrap_err();
+        assert_eq!(error.position, "0x200 if V0 ==".len());
+    }
+
+    #[test]
+    fn parses_address_condition_and_count() {
+        let breakpoint = Breakpoint::parse("  0x2A4 if V3 == 0x10 after 2").unwrap();
+        assert_eq!(breakpoint.address, Some(0x2A4));
+        assert!(breakpoint.condition.is_some());
+        assert_eq!(breakpoint.ignore_count, 2);
+
+        let error = Breakpoint::parse("0x2A4 when V3").unwrap_err();
+        assert_eq!(error.position, "0x2A4 ".len());
+    }
+
+    #[test]
+    fn needs_a_space_after_if() {
+        assert!(Breakpoint::parse("if V0 == 1").is_ok());
+        assert!(Breakpoint::parse("0x200 if\tV0 == 1").is_ok());
+        let error = Breakpoint::parse("0x200 ifV0 == 1").unwrap_err();
+        assert_eq!(error.position, "0x200 ".len());
+        assert!(Breakpoint::parse("ifV0==1").is_err());
+    }
+}
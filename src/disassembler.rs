@@ -0,0 +1,364 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::instruction::Instruction;
+use crate::opcode::Op;
+use crate::platform::Platform;
+
+const ROM_START: usize = 0x200;
+
+/// The assembly language listings are written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Syntax {
+    /// Cowgod's mnemonics, e.g. `LD V1, 0x20`, with addresses and raw opcodes
+    Classic,
+    /// Octo, e.g. `v1 := 0x20`, which Octo can assemble back into the ROM
+    Octo,
+}
+
+impl Syntax {
+    pub const ALL: [Self; 2] = [Syntax::Classic, Syntax::Octo];
+
+    /// The name for `--syntax` and the DAP launch argument
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syntax::Classic => "classic",
+            Syntax::Octo => "octo",
+        }
+    }
+
+    /// The syntax with that name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Where control can go after an instruction.
+enum Flow {
+    /// On to the next instruction
+    Next,
+    /// On to the next instruction or the one after it
+    Skip,
+    /// To the target and nowhere else
+    Jump(usize),
+    /// To the target, then back to the next instruction
+    Call(usize),
+    /// Nowhere we can follow: returns, exits and computed jumps
+    Stop,
+}
+
+/// One decoded instruction.
+struct Decoded {
+    /// 2, or 4 for the XO-CHIP F000 NNNN
+    len: usize,
+    flow: Flow,
+    /// Address the instruction refers to that deserves a label, e.g. ANNN
+    reference: Option<usize>,
+    classic: String,
+    octo: String,
+}
+
+/// Turns ROM bytes into an assembly listing.
+/// Code is found by following every path from 0x200, everything else is listed as data.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    platform: Platform,
+    code: BTreeSet<usize>,
+    labels: BTreeSet<usize>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom: &'a [u8], platform: Platform) -> Self {
+        let mut disassembler = Self {
+            rom,
+            platform,
+            code: BTreeSet::new(),
+            labels: BTreeSet::new(),
+        };
+        disassembler.trace();
+        disassembler
+    }
+
+    /// True if the byte at address was reached as the start of an instruction
+    pub fn is_code(&self, address: usize) -> bool {
+        self.code.contains(&address)
+    }
+
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let mut listing = String::new();
+        for (_, line) in self.lines(syntax) {
+            writeln!(listing, "{}", line).unwrap();
+        }
+        listing
+    }
+
+    /// The listing one line at a time, with the address of the instruction or data on each line.
+    /// Label lines have no address
+    pub fn lines(&self, syntax: Syntax) -> Vec<(Option<usize>, String)> {
+        let mut lines = vec![];
+        let end = ROM_START + self.rom.len();
+
+        let mut address = ROM_START;
+        while address < end {
+            if self.labels.contains(&address) {
+                match syntax {
+                    Syntax::Classic => lines.push((None, format!("{}:", label(address)))),
+                    Syntax::Octo => lines.push((None, format!(": {}", label(address)))),
+                }
+            }
+
+            if self.code.contains(&address) {
+                // an instruction with a label inside it, e.g. one the program rewrites, is listed
+                // as data split at the label so the label can be defined
+                let split = |decoded: &Decoded| (1..decoded.len).any(|i| self.labels.contains(&(address + i)));
+                if let Some(decoded) = self.decode(address).filter(|decoded| !split(decoded)) {
+                    let raw: String = (0..decoded.len).map(|i| format!("{:02X}", self.byte(address + i))).collect();
+                    match syntax {
+                        Syntax::Classic => lines.push((Some(address), format!("    {:#05X}: {:<8}  {}", address, raw, decoded.classic))),
+                        Syntax::Octo => lines.push((Some(address), format!("    {}", decoded.octo))),
+                    }
+                    address += decoded.len;
+                    continue;
+                }
+            }
+
+            // data runs until the next instruction or label, at most 8 bytes per line
+            let mut data_end = address + 1;
+            while data_end < end && data_end - address < 8 && !self.code.contains(&data_end) && !self.labels.contains(&data_end) {
+                data_end += 1;
+            }
+            let bytes: Vec<String> = (address..data_end).map(|a| format!("{:#04X}", self.byte(a))).collect();
+            match syntax {
+                Syntax::Classic => lines.push((Some(address), format!("    {:#05X}: DB {}", address, bytes.join(", ")))),
+                Syntax::Octo => lines.push((Some(address), format!("    {}", bytes.join(" ")))),
+            }
+            address = data_end;
+        }
+
+        lines
+    }
+
+    /// One instruction in the given syntax, without address or raw bytes, using this ROM's labels
+    pub fn format(&self, op: Op, syntax: Syntax) -> String {
+        let (classic, octo) = self.mnemonics(op);
+        match syntax {
+            Syntax::Classic => classic,
+            Syntax::Octo => octo,
+        }
+    }
+
+    fn byte(&self, address: usize) -> u8 {
+        self.rom[address - ROM_START]
+    }
+
+    fn in_rom(&self, address: usize, len: usize) -> bool {
+        address >= ROM_START && address + len <= ROM_START + self.rom.len()
+    }
+
+    fn word(&self, address: usize) -> Option<u16> {
+        if !self.in_rom(address, 2) {
+            return None;
+        }
+        Some(u16::from_be_bytes([self.byte(address), self.byte(address + 1)]))
+    }
+
+    /// Walk every path through the program from 0x200, marking instructions and jump targets
+    fn trace(&mut self) {
+        let mut pending = vec![ROM_START];
+        while let Some(address) = pending.pop() {
+            if self.code.contains(&address) {
+                continue;
+            }
+            let Some(decoded) = self.decode(address) else {
+                continue;
+            };
+            self.code.insert(address);
+
+            let next = address + decoded.len;
+            match decoded.flow {
+                Flow::Next => pending.push(next),
+                Flow::Skip => {
+                    pending.push(next);
+                    let skipped_len = if self.platform.has_xo_chip_instructions() && self.word(next) == Some(0xF000) { 4 } else { 2 };
+                    pending.push(next + skipped_len);
+                }
+                Flow::Jump(target) => {
+                    if self.in_rom(target, 2) {
+                        self.labels.insert(target);
+                        pending.push(target);
+                    }
+                }
+                Flow::Call(target) => {
+                    if self.in_rom(target, 2) {
+                        self.labels.insert(target);
+                        pending.push(target);
+                    }
+                    pending.push(next);
+                }
+                Flow::Stop => {}
+            }
+
+            if let Some(reference) = decoded.reference {
+                if self.in_rom(reference, 1) {
+                    self.labels.insert(reference);
+                }
+            }
+        }
+    }
+
+    fn decode(&self, address: usize) -> Option<Decoded> {
+        let instruction = Instruction::new(self.word(address)?);
+        let next_word = self.word(address + 2).unwrap_or(0);
+        let op = Op::decode(instruction, next_word, self.platform);
+        if let Op::LongIndex { .. } = op {
+            // the address half must be inside the ROM too
+            self.word(address + 2)?;
+        }
+
+        let (flow, reference) = match op {
+            Op::Return | Op::Exit | Op::JumpWithOffset { .. } => (Flow::Stop, None),
+            Op::Jump { nnn } => (Flow::Jump(nnn as usize), None),
+            Op::Call { nnn } => (Flow::Call(nnn as usize), None),
+            Op::SkipIfEqual { .. }
+            | Op::SkipIfNotEqual { .. }
+            | Op::SkipIfRegistersEqual { .. }
+            | Op::SkipIfRegistersNotEqual { .. }
+            | Op::SkipIfKey { .. }
+            | Op::SkipIfNotKey { .. } => (Flow::Skip, None),
+            Op::SetIndex { nnn } => (Flow::Next, Some(nnn as usize)),
+            Op::LongIndex { nnnn } => (Flow::Next, Some(nnnn as usize)),
+            // 0000 is far more often padding than a machine code call
+            Op::Unknown(_) | Op::MachineCall { .. } => return None,
+            _ => (Flow::Next, None),
+        };
+        let (classic, octo) = self.mnemonics(op);
+
+        Some(Decoded { len: op.size(), flow, reference, classic, octo })
+    }
+
+    /// The instruction in classic and Octo syntax
+    fn mnemonics(&self, op: Op) -> (String, String) {
+        let arithmetic = |classic: &str, octo: &str, x: usize, y: usize| {
+            (format!("{} V{:X}, V{:X}", classic, x, y), format!("v{:x} {} v{:x}", x, octo, y))
+        };
+
+        match op {
+            Op::ClearScreen => ("CLS".into(), "clear".into()),
+            Op::Return => ("RET".into(), "return".into()),
+            Op::ScrollDown { n } => (format!("SCD {}", n), format!("scroll-down {}", n)),
+            Op::ScrollUp { n } => (format!("SCU {}", n), format!("scroll-up {}", n)),
+            Op::ScrollRight => ("SCR".into(), "scroll-right".into()),
+            Op::ScrollLeft => ("SCL".into(), "scroll-left".into()),
+            Op::Exit => ("EXIT".into(), "exit".into()),
+            Op::Lores => ("LOW".into(), "lores".into()),
+            Op::Hires => ("HIGH".into(), "hires".into()),
+            // Octo has no mnemonic for machine code, only the bytes
+            Op::MachineCall { nnn } => (format!("SYS {:#05X}", nnn), format!("{:#04X} {:#04X}", nnn >> 8, nnn & 0xFF)),
+            Op::Jump { nnn } => {
+                let nnn = nnn as usize;
+                (format!("JP {}", self.address(nnn)), format!("jump {}", self.address(nnn)))
+            }
+            Op::Call { nnn } => {
+                let nnn = nnn as usize;
+                // Octo calls a label by naming it, a raw address needs :call
+                let octo = if self.in_rom(nnn, 1) { label(nnn) } else { format!(":call {:#05X}", nnn) };
+                (format!("CALL {}", self.address(nnn)), octo)
+            }
+            Op::SkipIfEqual { x, nn } => (format!("SE V{:X}, {:#04X}", x, nn), format!("if v{:x} != {:#04X} then", x, nn)),
+            Op::SkipIfNotEqual { x, nn } => (format!("SNE V{:X}, {:#04X}", x, nn), format!("if v{:x} == {:#04X} then", x, nn)),
+            Op::SkipIfRegistersEqual { x, y } => (format!("SE V{:X}, V{:X}", x, y), format!("if v{:x} != v{:x} then", x, y)),
+            Op::StoreRegisterRange { x, y } => (format!("SAVE V{:X} - V{:X}", x, y), format!("save v{:x} - v{:x}", x, y)),
+            Op::LoadRegisterRange { x, y } => (format!("LOAD V{:X} - V{:X}", x, y), format!("load v{:x} - v{:x}", x, y)),
+            Op::SetImm { x, nn } => (format!("LD V{:X}, {:#04X}", x, nn), format!("v{:x} := {:#04X}", x, nn)),
+            Op::AddImm { x, nn } => (format!("ADD V{:X}, {:#04X}", x, nn), format!("v{:x} += {:#04X}", x, nn)),
+            Op::Set { x, y } => arithmetic("LD", ":=", x, y),
+            Op::Or { x, y } => arithmetic("OR", "|=", x, y),
+            Op::And { x, y } => arithmetic("AND", "&=", x, y),
+            Op::Xor { x, y } => arithmetic("XOR", "^=", x, y),
+            Op::Add { x, y } => arithmetic("ADD", "+=", x, y),
+            Op::Sub { x, y } => arithmetic("SUB", "-=", x, y),
+            Op::ShiftRight { x, y } => arithmetic("SHR", ">>=", x, y),
+            Op::SubReverse { x, y } => arithmetic("SUBN", "=-", x, y),
+            Op::ShiftLeft { x, y } => arithmetic("SHL", "<<=", x, y),
+            Op::SkipIfRegistersNotEqual { x, y } => (format!("SNE V{:X}, V{:X}", x, y), format!("if v{:x} == v{:x} then", x, y)),
+            Op::SetIndex { nnn } => {
+                let nnn = nnn as usize;
+                (format!("LD I, {}", self.address(nnn)), format!("i := {}", self.address(nnn)))
+            }
+            Op::JumpWithOffset { nnn } => (format!("JP V0, {:#05X}", nnn), format!("jump0 {:#05X}", nnn)),
+            Op::Random { x, nn } => (format!("RND V{:X}, {:#04X}", x, nn), format!("v{:x} := random {:#04X}", x, nn)),
+            Op::Draw { x, y, n } => (format!("DRW V{:X}, V{:X}, {}", x, y, n), format!("sprite v{:x} v{:x} {}", x, y, n)),
+            Op::SkipIfKey { x } => (format!("SKP V{:X}", x), format!("if v{:x} -key then", x)),
+            Op::SkipIfNotKey { x } => (format!("SKNP V{:X}", x), format!("if v{:x} key then", x)),
+            Op::LongIndex { nnnn } => {
+                let nnnn = nnnn as usize;
+                (format!("LD I, LONG {}", self.address(nnnn)), format!("i := long {}", self.address(nnnn)))
+            }
+            Op::SelectPlanes { n } => (format!("PLANE {}", n), format!("plane {}", n)),
+            Op::LoadAudioPattern => ("AUDIO".into(), "audio".into()),
+            Op::GetDelay { x } => (format!("LD V{:X}, DT", x), format!("v{:x} := delay", x)),
+            Op::WaitKey { x } => (format!("LD V{:X}, K", x), format!("v{:x} := key", x)),
+            Op::SetDelay { x } => (format!("LD DT, V{:X}", x), format!("delay := v{:x}", x)),
+            Op::SetSound { x } => (format!("LD ST, V{:X}", x), format!("buzzer := v{:x}", x)),
+            Op::AddIndex { x } => (format!("ADD I, V{:X}", x), format!("i += v{:x}", x)),
+            Op::FontCharacter { x } => (format!("LD F, V{:X}", x), format!("i := hex v{:x}", x)),
+            Op::BigFontCharacter { x } => (format!("LD HF, V{:X}", x), format!("i := bighex v{:x}", x)),
+            Op::BinaryCodedDecimal { x } => (format!("LD B, V{:X}", x), format!("bcd v{:x}", x)),
+            Op::Pitch { x } => (format!("PITCH V{:X}", x), format!("pitch := v{:x}", x)),
+            Op::Store { x } => (format!("LD [I], V{:X}", x), format!("save v{:x}", x)),
+            Op::Load { x } => (format!("LD V{:X}, [I]", x), format!("load v{:x}", x)),
+            Op::StoreFlags { x } => (format!("LD R, V{:X}", x), format!("saveflags v{:x}", x)),
+            Op::LoadFlags { x } => (format!("LD V{:X}, R", x), format!("loadflags v{:x}", x)),
+            Op::Unknown(instruction) => (format!("DW {:#06X}", instruction), format!("{:#06X}", instruction)),
+        }
+    }
+
+    /// A label for addresses inside the ROM, a plain number otherwise
+    fn address(&self, address: usize) -> String {
+        if self.in_rom(address, 1) {
+            label(address)
+        } else {
+            format!("{:#05X}", address)
+        }
+    }
+}
+
+pub(crate) fn label(address: usize) -> String {
+    format!("label_{:03X}", address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_octo() {
+        // CLS, call 0x206, jump to itself, sprite data the call leaves I pointing at
+        let rom = [0x00, 0xE0, 0x22, 0x06, 0x12, 0x04, 0xA2, 0x0A, 0x00, 0xEE, 0xF0, 0x90];
+        let listing = Disassembler::new(&rom, Platform::Chip8).listing(Syntax::Octo);
+        let expected = [
+            "    clear",
+            "    label_206",
+            ": label_204",
+            "    jump label_204",
+            ": label_206",
+            "    i := label_20A",
+            "    return",
+            ": label_20A",
+            "    0xF0 0x90",
+        ];
+        assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn defines_labels_inside_instructions() {
+        // i := 0x203, the second byte of the jump
+        let rom = [0xA2, 0x03, 0x12, 0x00];
+        let listing = Disassembler::new(&rom, Platform::Chip8).listing(Syntax::Octo);
+        assert_eq!(listing.lines().collect::<Vec<_>>(), [": label_200", "    i := label_203", "    0x12", ": label_203", "    0x00"]);
+
+        let lines = Disassembler::new(&rom, Platform::Chip8).lines(Syntax::Classic);
+        assert_eq!(lines[2], (Some(0x202), "    0x202: DB 0x12".to_string()));
+    }
+}
@@ -1,112 +1,231 @@
-use sdl2::{pixels::Color, rect::Point};
+use crate::error::EmulatorError;
+use crate::frontend::FrameSink;
+use crate::save_state::{StateReader, StateWriter};
 
-use crate::{memory::Memory, sdl_system::SdlSystem};
-
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+const LORES_WIDTH: usize = 64;
+const LORES_HEIGHT: usize = 32;
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
 
+/// Each byte of the buffer holds one pixel, with bit 0 set if the pixel is lit in the first
+/// plane and bit 1 if it is lit in the second (XO-CHIP) plane
 pub struct Display {
-    buffer: [u8; WIDTH * HEIGHT],
+    buffer: [u8; HIRES_WIDTH * HIRES_HEIGHT],
+    hires: bool,
+    selected_planes: u8,
     dirty: bool,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
-            buffer: [0; WIDTH * HEIGHT],
+            buffer: [0; HIRES_WIDTH * HIRES_HEIGHT],
+            hires: false,
+            selected_planes: 0x1,
             dirty: false,
         }
     }
 
-    pub fn clear(&mut self) {
-        self.buffer = [0; 64 * 32];
+    pub fn width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
     }
 
-    pub fn draw(&mut self, sdl: &mut SdlSystem) {
-        if !self.dirty {
-            return;
-        }
-
-        sdl.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        sdl.canvas.clear();
+    pub fn height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
 
-        let (u_width, u_height) = sdl.canvas.output_size().unwrap();
-        let x_step = u_width as usize / WIDTH;
-        let y_step = u_height as usize / HEIGHT;
+    /// The visible pixels, width * height bytes laid out row by row, see `FrameSink`
+    pub fn pixels(&self) -> &[u8] {
+        &self.buffer[..self.width() * self.height()]
+    }
 
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let pixel_idx = (y * WIDTH + x) as usize;
-                let pixel = self.buffer[pixel_idx];
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
 
-                
-                if pixel == 0 {
-                    //print!("_");
-                    continue;
+    /// Switch between 64x32 and the SUPER-CHIP 128x64 resolution. XO-CHIP clears every plane.
+    /// SUPER-CHIP 1.1 keeps the picture, its screen is always 128x64 with lores pixels drawn 2x2,
+    /// so going to hires doubles each pixel and going to lores keeps every other one
+    pub fn set_hires(&mut self, hires: bool, clear: bool) {
+        if clear {
+            self.buffer = [0; HIRES_WIDTH * HIRES_HEIGHT];
+        } else if hires != self.hires {
+            let previous = self.buffer;
+            self.buffer = [0; HIRES_WIDTH * HIRES_HEIGHT];
+            let (width, height) = if hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (LORES_WIDTH, LORES_HEIGHT) };
+            for y in 0..height {
+                for x in 0..width {
+                    self.buffer[y * width + x] = if hires {
+                        previous[y / 2 * LORES_WIDTH + x / 2]
+                    } else {
+                        previous[y * 2 * HIRES_WIDTH + x * 2]
+                    };
                 }
-                //print!("X");
-
-                let color = Color::RGB(255, 255, 255);
-                /*
-                let color = if pixel == 1 {
-                    Color::RGB(255, 255, 255)
-                } else {
-                    Color::RGB(0, 0, 0)
-                };*/
-
-                let sdl_x = i32::try_from(x * x_step).unwrap();
-                let sdl_y = i32::try_from(y * y_step).unwrap();
-                
-                let sdl_point = Point::new(sdl_x, sdl_y);
-                let size = Point::new(x_step as i32, y_step as i32);
-                sdl.draw_filled_rect(sdl_point, size, color);
             }
+        }
+        self.hires = hires;
+        self.dirty = true;
+    }
+
+    /// Select which planes clear, scroll and draw operate on (XO-CHIP FN01)
+    pub fn select_planes(&mut self, planes: u8) {
+        self.selected_planes = planes & 0x3;
+    }
 
-            //println!("");
+    pub fn selected_plane_count(&self) -> usize {
+        self.selected_planes.count_ones() as usize
+    }
+
+    pub fn clear(&mut self) {
+        let planes = self.selected_planes;
+        for pixel in self.buffer.iter_mut() {
+            *pixel &= !planes;
         }
+        self.dirty = true;
+    }
 
-        //println!("");
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.array(&self.buffer);
+        state.bool(self.hires);
+        state.u8(self.selected_planes);
+    }
 
-        sdl.canvas.present();
+    /// The whole screen is redrawn after loading
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmulatorError> {
+        self.buffer.copy_from_slice(state.array(HIRES_WIDTH * HIRES_HEIGHT)?);
+        self.hires = state.bool()?;
+        self.selected_planes = state.u8()? & 0x3;
+        self.dirty = true;
+        Ok(())
+    }
 
-        self.dirty = false;
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
     }
 
-    pub fn xor_sprite(&mut self, x: u8, y: u8, num_rows: u8, memory: &Memory, index_register: usize) -> u8 {
-        let x16 = x as u16;
-        let y16 = y as u16;
-        let num_rows_16 = num_rows as u16;
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
 
-        let xr = x16 & (WIDTH - 1) as u16;
-        let yr = y16 & (HEIGHT - 1) as u16;
+    pub fn scroll_right(&mut self, columns: usize) {
+        self.scroll(columns as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.scroll(-(columns as isize), 0);
+    }
 
-        let mut vf = 0;
+    /// Move the selected planes by dx, dy pixels, filling the exposed area with unlit pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width(), self.height());
+        let planes = self.selected_planes;
+        let previous = self.buffer;
+
+        for y in 0..height {
+            for x in 0..width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let src = if src_x >= 0 && src_x < width as isize && src_y >= 0 && src_y < height as isize {
+                    previous[src_y as usize * width + src_x as usize] & planes
+                } else {
+                    0
+                };
 
-        for row_idx in 0..num_rows_16 {
-            let sprite = memory.read_u8(index_register + row_idx as usize);
-            let y = yr + row_idx;
-            if y >= HEIGHT as u16 {
-                return vf;
+                let pixel_idx = y * width + x;
+                self.buffer[pixel_idx] = (self.buffer[pixel_idx] & !planes) | src;
             }
+        }
+
+        self.dirty = true;
+    }
+
+    /// Hand the screen to the frontend if it has changed since the last draw
+    pub fn draw(&mut self, screen: &mut dyn FrameSink) {
+        if !self.dirty {
+            return;
+        }
+
+        screen.present(self.width(), self.height(), self.pixels());
 
-            for col_idx in 0..8 {
-                let x = xr + col_idx;
-                if x >= WIDTH as u16 {
-                    break;
+        self.dirty = false;
+    }
+
+    /// XOR a sprite onto the selected planes. Each row of the sprite is sprite_width (8 or 16) bits wide,
+    /// stored big endian in sprite_width / 8 bytes. When more than one plane is selected the sprite
+    /// holds the data for each plane in turn, lowest plane first.
+    /// Returns the number of rows in which a pixel was turned off and the number of rows that were
+    /// clipped off the bottom of the screen (SUPER-CHIP reports the sum of both in VF in hires mode).
+    /// When clip is false, sprites crossing the screen edge wrap around to the other side
+    pub fn xor_sprite(&mut self, x: u8, y: u8, sprite: &[u8], sprite_width: usize, clip: bool) -> (u8, u8) {
+        let (width, height) = (self.width(), self.height());
+        let bytes_per_row = sprite_width / 8;
+
+        let xr = x as usize & (width - 1);
+        let yr = y as usize & (height - 1);
+
+        // bit masks of the sprite rows, so a row is only counted once across planes
+        let mut collided_rows: u32 = 0;
+        let mut clipped_rows: u32 = 0;
+
+        // DXY0 outside SUPER-CHIP draws a sprite with no rows
+        let plane_count = self.selected_plane_count();
+        if plane_count == 0 || sprite.is_empty() {
+            return (0, 0);
+        }
+        let plane_len = sprite.len() / plane_count;
+        let planes = (0..2).map(|plane_idx| 1 << plane_idx).filter(|plane| self.selected_planes & plane != 0);
+
+        for (plane, plane_sprite) in planes.zip(sprite.chunks_exact(plane_len)) {
+            for (row_idx, row) in plane_sprite.chunks_exact(bytes_per_row).enumerate() {
+                let mut y = yr + row_idx;
+                if y >= height {
+                    if clip {
+                        clipped_rows |= 1 << row_idx;
+                        continue;
+                    }
+                    y %= height;
                 }
 
-                let pixel_idx = (y * WIDTH as u16 + x) as usize;
-                let sprite_bit = (sprite >> (7 - col_idx)) & 0x1;
-                if sprite_bit == 1 && self.buffer[pixel_idx] == 1 {
-                    self.buffer[pixel_idx] = 0;
-                    vf = 1; // set VF to 1? overflow register? https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#dxyn-display
-                } else if sprite_bit == 1 && self.buffer[pixel_idx] == 0 {
-                    self.buffer[pixel_idx] = 1;
+                for col_idx in 0..sprite_width {
+                    let mut x = xr + col_idx;
+                    if x >= width {
+                        if clip {
+                            break;
+                        }
+                        x %= width;
+                    }
+
+                    let pixel_idx = y * width + x;
+                    let sprite_bit = (row[col_idx / 8] >> (7 - col_idx % 8)) & 0x1;
+                    if sprite_bit == 1 && self.buffer[pixel_idx] & plane != 0 {
+                        self.buffer[pixel_idx] &= !plane;
+                        collided_rows |= 1 << row_idx; // set VF to 1? overflow register? https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#dxyn-display
+                    } else if sprite_bit == 1 {
+                        self.buffer[pixel_idx] |= plane;
+                    }
                 }
             }
         }
 
         self.dirty = true;
-        vf
+        (collided_rows.count_ones() as u8, clipped_rows.count_ones() as u8)
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_sprite_draws_nothing() {
+        let mut display = Display::new();
+        assert_eq!(display.xor_sprite(3, 4, &[], 8, true), (0, 0));
+        assert!(display.buffer.iter().all(|&pixel| pixel == 0));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,59 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can stop the emulator, from a bad ROM file to a program doing something
+/// the hardware cannot.
+#[derive(Debug)]
+pub enum EmulatorError {
+    /// The instruction at address does not exist on the current platform
+    UnknownOpcode { opcode: u16, address: usize },
+    /// A 0NNN machine code call, with `MachineCallPolicy::Error`
+    MachineCall { nnn: u16, address: usize },
+    /// A subroutine call with the stack already full
+    StackOverflow,
+    /// A return with no subroutine to return from
+    StackUnderflow,
+    /// A read or write past the end of memory
+    MemoryOutOfRange { address: usize },
+    /// With strict memory, a write over the interpreter and fonts below 0x200
+    ProtectedWrite { address: usize },
+    /// The ROM does not fit in the memory after 0x200
+    RomTooLarge { size: usize, max: usize },
+    /// A dump of the COSMAC VIP interpreter that isn't its 512 bytes
+    BadInterpreter { size: usize },
+    /// A save state that is damaged or from an incompatible version
+    InvalidSaveState { reason: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { opcode, address } => write!(f, "unknown opcode {:#06X} at {:#05X}", opcode, address),
+            EmulatorError::MachineCall { nnn, address } => write!(f, "machine code call to {:#05X} at {:#05X} can't be emulated", nnn, address),
+            EmulatorError::StackOverflow => write!(f, "stack overflow"),
+            EmulatorError::StackUnderflow => write!(f, "stack underflow, return without a call"),
+            EmulatorError::MemoryOutOfRange { address } => write!(f, "memory access out of range at {:#06X}", address),
+            EmulatorError::ProtectedWrite { address } => write!(f, "write to {:#05X}, below 0x200 where the interpreter and fonts are", address),
+            EmulatorError::RomTooLarge { size, max } => write!(f, "ROM is {} bytes, the most that fits is {} bytes", size, max),
+            EmulatorError::BadInterpreter { size } => write!(f, "COSMAC VIP interpreter is {} bytes, it should be 512", size),
+            EmulatorError::InvalidSaveState { reason } => write!(f, "invalid save state, {}", reason),
+            EmulatorError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmulatorError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(error: io::Error) -> Self {
+        EmulatorError::Io(error)
+    }
+}
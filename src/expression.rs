@@ -0,0 +1,381 @@
+use std::fmt;
+
+use crate::computer::Computer;
+
+/// A parse failure, position is the byte offset in the source where it went wrong
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Machine state an expression can refer to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variable {
+    Register(usize),
+    Index,
+    ProgramCounter,
+    StackDepth,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    /// The byte at an address
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// longest first, so <= is not read as <
+const SYMBOLS: [&str; 19] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]", "="];
+
+/// A condition over the machine state, e.g. `V3 == 0x10 && I > 0x300` or `mem[0x2F0] != 0`.
+///
+/// Values are integers and conditions are true when non-zero, as in C. The names are
+/// V0 to VF, I, PC, SP (stack depth), DT or delay_timer, ST or sound_timer, and mem[ADDRESS]
+/// for a byte of memory, all case insensitive. Operators from loosest to tightest:
+/// `||`, `&&`, comparisons, `|`, `^`, `&`, `+ -`, then unary `! -`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, next: 0, end: source.len() };
+        let root = parser.or()?;
+        if let Some((position, _)) = parser.peek() {
+            return Err(ParseError { position, message: "unexpected input".into() });
+        }
+
+        Ok(Self { source: source.trim().to_string(), root })
+    }
+
+    pub fn evaluate(&self, computer: &Computer) -> i64 {
+        evaluate(&self.root, computer)
+    }
+
+    pub fn is_true(&self, computer: &Computer) -> bool {
+        self.evaluate(computer) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = vec![];
+    let mut position = 0;
+
+    while position < source.len() {
+        let rest = &source[position..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            position += c.len_utf8();
+        } else if c.is_ascii_digit() {
+            let length = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let text = &rest[..length];
+            let number = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => text.parse(),
+            };
+            let number = number.map_err(|_| ParseError { position, message: format!("bad number {}", text) })?;
+            tokens.push((position, Token::Number(number)));
+            position += length;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            tokens.push((position, Token::Name(rest[..length].to_ascii_lowercase())));
+            position += length;
+        } else if let Some(&symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            // a single = means ==, there is no assignment
+            let token = if symbol == "=" { "==" } else { symbol };
+            tokens.push((position, Token::Symbol(token)));
+            position += symbol.len();
+        } else {
+            return Err(ParseError { position, message: format!("unexpected character {}", c) });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Where errors at the end of the input are reported
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, &Token)> {
+        self.tokens.get(self.next).map(|(position, token)| (*position, token))
+    }
+
+    fn position(&self) -> usize {
+        self.peek().map_or(self.end, |(position, _)| position)
+    }
+
+    /// Consume the next token if it is one of the symbols
+    fn symbol(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some((_, Token::Symbol(symbol))) if symbols.contains(symbol) => {
+                let symbol = *symbol;
+                self.next += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        match self.symbol(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(ParseError { position: self.position(), message: format!("expected {}", symbol) }),
+        }
+    }
+
+    /// One level of left associative binary operators, operands parsed by operand
+    fn binary(&mut self, operators: &[(&'static str, BinaryOp)], operand: fn(&mut Self) -> Result<Node, ParseError>) -> Result<Node, ParseError> {
+        let symbols: Vec<&'static str> = operators.iter().map(|(symbol, _)| *symbol).collect();
+        let mut left = operand(self)?;
+        while let Some(symbol) = self.symbol(&symbols) {
+            let op = operators.iter().find(|(s, _)| *s == symbol).unwrap().1;
+            let right = operand(self)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[
+            ("==", BinaryOp::Equal),
+            ("!=", BinaryOp::NotEqual),
+            ("<", BinaryOp::Less),
+            ("<=", BinaryOp::LessEqual),
+            (">", BinaryOp::Greater),
+            (">=", BinaryOp::GreaterEqual),
+        ], Self::bit_or)
+    }
+
+    fn bit_or(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("|", BinaryOp::BitOr)], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("^", BinaryOp::BitXor)], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("&", BinaryOp::BitAnd)], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Node, ParseError> {
+        self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        match self.symbol(&["!", "-"]) {
+            Some("!") => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(_) => Ok(Node::Negate(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        let position = self.position();
+        let Some((_, token)) = self.peek() else {
+            return Err(ParseError { position, message: "expected a value".into() });
+        };
+        let token = token.clone();
+        self.next += 1;
+
+        match token {
+            Token::Number(number) => Ok(Node::Number(number)),
+            Token::Symbol("(") => {
+                let node = self.or()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Name(name) if name == "mem" => {
+                self.expect("[")?;
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(address)))
+            }
+            Token::Name(name) => variable(&name)
+                .map(Node::Variable)
+                .ok_or(ParseError { position, message: format!("unknown name {}", name) }),
+            Token::Symbol(symbol) => Err(ParseError { position, message: format!("unexpected {}", symbol) }),
+        }
+    }
+}
+
+fn variable(name: &str) -> Option<Variable> {
+    let variable = match name {
+        "i" => Variable::Index,
+        "pc" => Variable::ProgramCounter,
+        "sp" => Variable::StackDepth,
+        "dt" | "delay_timer" => Variable::DelayTimer,
+        "st" | "sound_timer" => Variable::SoundTimer,
+        _ => {
+            let digit = name.strip_prefix('v')?;
+            if digit.len() != 1 {
+                return None;
+            }
+            Variable::Register(usize::from_str_radix(digit, 16).ok()?)
+        }
+    };
+    Some(variable)
+}
+
+fn evaluate(node: &Node, computer: &Computer) -> i64 {
+    match node {
+        Node::Number(number) => *number,
+        Node::Variable(variable) => match variable {
+            Variable::Register(x) => computer.registers()[*x] as i64,
+            Variable::Index => computer.index_register() as i64,
+            Variable::ProgramCounter => computer.program_counter() as i64,
+            Variable::StackDepth => computer.stack().depth() as i64,
+            Variable::DelayTimer => computer.delay_timer() as i64,
+            Variable::SoundTimer => computer.sound_timer() as i64,
+        },
+        // reading outside memory is not an error here, there is just nothing there
+        Node::Memory(address) => usize::try_from(evaluate(address, computer))
+            .ok()
+            .and_then(|address| computer.memory().read_u8(address).ok())
+            .unwrap_or(0) as i64,
+        Node::Not(operand) => (evaluate(operand, computer) == 0) as i64,
+        Node::Negate(operand) => evaluate(operand, computer).wrapping_neg(),
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, computer);
+            // && and || short circuit
+            match op {
+                BinaryOp::Or if left != 0 => return 1,
+                BinaryOp::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, computer);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                BinaryOp::Equal => (left == right) as i64,
+                BinaryOp::NotEqual => (left != right) as i64,
+                BinaryOp::Less => (left < right) as i64,
+                BinaryOp::LessEqual => (left <= right) as i64,
+                BinaryOp::Greater => (left > right) as i64,
+                BinaryOp::GreaterEqual => (left >= right) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Platform;
+
+    /// A computer at 0x200 with V0 = 1, V3 = 0x10, I = 0x300 and 0xAB at 0x300
+    fn computer() -> Computer {
+        let mut computer = Computer::new(Platform::Chip8, Platform::Chip8.default_quirks());
+        computer.load_program(&[0x12, 0x00]).unwrap();
+        computer.set_register(0x0, 1);
+        computer.set_register(0x3, 0x10);
+        computer.set_index_register(0x300);
+        computer.memory_mut().load(0x300, &[0xAB]).unwrap();
+        computer
+    }
+
+    fn evaluate(source: &str) -> i64 {
+        Expression::parse(source).unwrap().evaluate(&computer())
+    }
+
+    #[test]
+    fn binds_operators_by_precedence() {
+        assert_eq!(evaluate("1 + 2 & 3"), 3);
+        assert_eq!(evaluate("8 | 1 ^ 3 & 6"), 11);
+        assert_eq!(evaluate("1 | 2 == 3"), 1);
+        assert_eq!(evaluate("0 && 1 || 1"), 1);
+        assert_eq!(evaluate("1 || 1 && 0"), 1);
+        assert_eq!(evaluate("10 - 3 - 2"), 5);
+        assert_eq!(evaluate("-(2 + 3) + !0"), -4);
+        assert_eq!(evaluate("(1 | 2) == 3"), 1);
+    }
+
+    #[test]
+    fn reads_the_machine() {
+        assert!(Expression::parse("V3 == 0x10 && i >= 0x300").unwrap().is_true(&computer()));
+        assert_eq!(evaluate("v0 + V3 + pc + SP"), 1 + 0x10 + 0x200);
+        assert_eq!(evaluate("DT = 0 && sound_timer == 0"), 1);
+        assert_eq!(evaluate("mem[I]"), 0xAB);
+        assert_eq!(evaluate("MEM[0x2FF + v0] - 0xAB"), 0);
+        // outside memory reads as nothing rather than failing
+        assert_eq!(evaluate("mem[0x10000] | mem[-1]"), 0);
+    }
+
+    #[test]
+    fn reports_where_it_went_wrong() {
+        let error = |source| Expression::parse(source).unwrap_err();
+        assert_eq!(error("V0 == "), ParseError { position: 6, message: "expected a value".into() });
+        assert_eq!(error("V0 == VG").position, 6);
+        assert_eq!(error("(V0 == 1").message, "expected )");
+        assert_eq!(error("mem 0x200").message, "expected [");
+        assert_eq!(error("0x2G0").message, "bad number 0x2G0");
+        assert_eq!(error("V0 # 1").position, 3);
+        assert_eq!(error("V0 1").message, "unexpected input");
+    }
+}
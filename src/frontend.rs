@@ -0,0 +1,29 @@
+//! The interfaces the emulator core uses to talk to the outside world, so the same `Computer`
+//! can drive an SDL window or run headless.
+
+/// Receives the screen contents whenever they change.
+/// Each pixel is a byte with bit 0 set if it is lit in the first plane and bit 1 if it is lit in
+/// the second (XO-CHIP) plane, laid out row by row.
+pub trait FrameSink {
+    fn present(&mut self, width: usize, height: usize, pixels: &[u8]);
+}
+
+/// The 16 key hexadecimal keypad, keys 0x0 to 0xF.
+pub trait Keypad {
+    fn is_key_down(&self, key: u8) -> bool;
+}
+
+/// What the buzzer should be playing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tone {
+    /// A plain beep in whatever voice the frontend chooses
+    Beep,
+    /// The XO-CHIP 1-bit audio pattern, played at 4000 * 2^((pitch - 64) / 48) bits per second
+    Pattern { pattern: [u8; 16], pitch: u8 },
+}
+
+/// Makes sound while the sound timer is non-zero.
+pub trait AudioOutput {
+    /// Called once per frame, with None when the buzzer should be silent
+    fn play(&mut self, tone: Option<Tone>);
+}
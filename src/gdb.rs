@@ -0,0 +1,465 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::computer::Computer;
+use crate::debugger::{Breakpoint, Debugger};
+use crate::frontend::Keypad;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rust-chip-8.core">
+    <reg name="v0" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Register numbers after V0 to VF, in the order of the target description
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+const REGISTER_COUNT: usize = 21;
+
+// stop signals
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// A GDB remote serial protocol server, so gdb, lldb and other front-ends can debug a ROM.
+/// It listens on localhost and is polled once per frame, so the emulator never blocks on the
+/// socket. The program stays paused until a client connects and continues it.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    /// Bytes received that don't yet make a whole packet
+    received: Vec<u8>,
+    debugger: Debugger,
+}
+
+impl GdbServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            client: None,
+            received: vec![],
+            debugger: Debugger::new(),
+        })
+    }
+
+    pub fn port(&self) -> io::Result<u16> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Handle whatever the client sent, then emulate one 60Hz frame unless paused.
+    /// A client that misbehaves or goes away is dropped and the program carries on running
+    pub fn run_frame(&mut self, computer: &mut Computer, keypad: &dyn Keypad) {
+        if self.client.is_none() {
+            self.accept();
+        }
+
+        if self.client.is_some() && self.receive(computer, keypad).is_err() {
+            self.disconnect();
+        }
+
+        match self.debugger.run_frame(computer, keypad) {
+            Ok(Some(_)) => self.send_or_disconnect(&format!("S{:02x}", SIGTRAP)),
+            Ok(None) => {}
+            Err(_) => self.send_or_disconnect(&format!("S{:02x}", SIGILL)),
+        }
+
+        if computer.has_exited() && !self.debugger.is_paused() {
+            self.send_or_disconnect("W00");
+            self.debugger.pause();
+        }
+    }
+
+    fn accept(&mut self) {
+        let Ok((stream, _)) = self.listener.accept() else {
+            return;
+        };
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        stream.set_nodelay(true).ok();
+
+        self.client = Some(stream);
+        self.received.clear();
+        self.debugger = Debugger::new();
+    }
+
+    /// Forget the client and its breakpoints, and let the program run freely
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.debugger = Debugger::new();
+        self.debugger.resume();
+    }
+
+    fn receive(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> io::Result<()> {
+        let Some(client) = self.client.as_mut() else {
+            return Ok(());
+        };
+
+        let mut buffer = [0; 4096];
+        loop {
+            match client.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            if let Some(reply) = self.handle(&packet, computer, keypad) {
+                self.send(&reply)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pull the next packet out of the received bytes, acknowledging it. Acks from the client
+    /// are skipped and a Ctrl-C interrupt pauses the program
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.received.first() {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(0x03) => {
+                    self.received.remove(0);
+                    if !self.debugger.is_paused() {
+                        self.debugger.pause();
+                        self.send(&format!("S{:02x}", SIGINT))?;
+                    }
+                }
+                Some(_) => {
+                    self.received.remove(0);
+                }
+            }
+        }
+
+        // $data#xx
+        let Some(end) = self.received.iter().position(|&byte| byte == b'#') else {
+            return Ok(None);
+        };
+        if self.received.len() < end + 3 {
+            return Ok(None);
+        }
+
+        let data: Vec<u8> = self.received[1..end].to_vec();
+        let checksum = std::str::from_utf8(&self.received[end + 1..end + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        self.received.drain(..end + 3);
+
+        let client = self.client.as_mut().ok_or(ErrorKind::NotConnected)?;
+        if checksum != Some(data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))) {
+            client.write_all(b"-")?;
+            return self.next_packet();
+        }
+        client.write_all(b"+")?;
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let client = self.client.as_mut().ok_or(ErrorKind::NotConnected)?;
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        client.write_all(format!("${}#{:02x}", data, checksum).as_bytes())
+    }
+
+    fn send_or_disconnect(&mut self, data: &str) {
+        if self.client.is_some() && self.send(data).is_err() {
+            self.disconnect();
+        }
+    }
+
+    /// The reply to a packet, None for commands that are answered later, like continue
+    fn handle(&mut self, packet: &str, computer: &mut Computer, keypad: &dyn Keypad) -> Option<String> {
+        let reply = match packet.as_bytes().first()? {
+            b'?' => format!("S{:02x}", SIGTRAP),
+            b'g' => (0..REGISTER_COUNT).map(|register| read_register(computer, register)).collect(),
+            b'G' => {
+                let mut hex = &packet[1..];
+                for register in 0..REGISTER_COUNT {
+                    let width = register_size(register) * 2;
+                    if hex.len() < width {
+                        break;
+                    }
+                    write_register(computer, register, &hex[..width]);
+                    hex = &hex[width..];
+                }
+                "OK".into()
+            }
+            b'p' => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(register) if register < REGISTER_COUNT => read_register(computer, register),
+                _ => "E01".into(),
+            },
+            b'P' => {
+                let register = packet[1..].split_once('=').and_then(|(register, value)| {
+                    let register = usize::from_str_radix(register, 16).ok()?;
+                    (register < REGISTER_COUNT).then_some((register, value))
+                });
+                match register {
+                    Some((register, value)) => {
+                        write_register(computer, register, value);
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            b'm' => read_memory(computer, &packet[1..]).unwrap_or_else(|| "E01".into()),
+            b'M' => write_memory(computer, &packet[1..]).unwrap_or_else(|| "E01".into()),
+            b'c' => {
+                self.debugger.resume();
+                return None;
+            }
+            b's' => match self.debugger.step(computer, keypad) {
+                Ok(()) => format!("S{:02x}", SIGTRAP),
+                Err(_) => format!("S{:02x}", SIGILL),
+            },
+            b'Z' | b'z' => self.breakpoint(packet).unwrap_or_else(|| "E01".into()),
+            b'D' => {
+                self.send("OK").ok();
+                self.disconnect();
+                return None;
+            }
+            b'k' => {
+                self.disconnect();
+                return None;
+            }
+            b'H' => "OK".into(),
+            b'q' => self.query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Z0,addr,kind adds a software breakpoint and z0 removes it, other kinds are not supported
+    fn breakpoint(&mut self, packet: &str) -> Option<String> {
+        let mut fields = packet[1..].split(',');
+        if fields.next() != Some("0") {
+            return Some(String::new());
+        }
+        let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+
+        let existing = self.debugger.breakpoints().iter().position(|breakpoint| breakpoint.address == Some(address) && breakpoint.condition.is_none());
+        match (packet.starts_with('Z'), existing) {
+            (true, None) => {
+                self.debugger.add_breakpoint(Breakpoint::at(address));
+            }
+            (false, Some(index)) => {
+                self.debugger.remove_breakpoint(index);
+            }
+            _ => {}
+        }
+        Some("OK".into())
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+".into()
+        } else if packet == "qAttached" {
+            "1".into()
+        } else if packet == "qfThreadInfo" {
+            "m1".into()
+        } else if packet == "qsThreadInfo" {
+            "l".into()
+        } else if packet == "qC" {
+            "QC1".into()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            // a window of the document, m if there is more to come, l for the last part
+            let Some((offset, length)) = parse_address_length(range) else {
+                return "E01".into();
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(length).min(xml.len());
+            let more = if end < xml.len() { 'm' } else { 'l' };
+            format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+        } else {
+            String::new()
+        }
+    }
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        REGISTER_I | REGISTER_PC => 2,
+        _ => 1,
+    }
+}
+
+/// Registers are sent as little endian hex
+fn read_register(computer: &Computer, register: usize) -> String {
+    let value = match register {
+        0..=0xF => computer.registers()[register] as usize,
+        REGISTER_I => computer.index_register(),
+        REGISTER_PC => computer.program_counter(),
+        REGISTER_SP => computer.stack().depth(),
+        REGISTER_DT => computer.delay_timer() as usize,
+        REGISTER_ST => computer.sound_timer() as usize,
+        _ => 0,
+    };
+    (0..register_size(register)).map(|i| format!("{:02x}", (value >> (8 * i)) & 0xFF)).collect()
+}
+
+/// The stack depth can't be written, it only changes by calling and returning
+fn write_register(computer: &mut Computer, register: usize, hex: &str) {
+    let Some(bytes) = decode_hex(hex) else {
+        return;
+    };
+    let value = bytes.iter().rev().fold(0usize, |value, &byte| value << 8 | byte as usize);
+    match register {
+        0..=0xF => computer.set_register(register, value as u8),
+        REGISTER_I => computer.set_index_register(value),
+        REGISTER_PC => computer.set_program_counter(value),
+        REGISTER_DT => computer.set_delay_timer(value as u8),
+        REGISTER_ST => computer.set_sound_timer(value as u8),
+        _ => {}
+    }
+}
+
+/// addr,length. The length comes from the client, so it is checked against memory before
+/// anything is allocated
+fn read_memory(computer: &Computer, arguments: &str) -> Option<String> {
+    let (address, length) = parse_address_length(arguments)?;
+    address.checked_add(length).filter(|&end| end <= computer.memory().size())?;
+    let mut bytes = vec![0; length];
+    computer.memory().read_u8_array(address, &mut bytes).ok()?;
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// addr,length:bytes
+fn write_memory(computer: &mut Computer, arguments: &str) -> Option<String> {
+    let (range, hex) = arguments.split_once(':')?;
+    let (address, length) = parse_address_length(range)?;
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != length {
+        return None;
+    }
+    computer.memory_mut().load(address, &bytes).ok()?;
+    Some("OK".into())
+}
+
+/// addr,length in hex
+fn parse_address_length(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    fn handle(gdb: &mut GdbServer, computer: &mut Computer, packet: &str) -> Option<String> {
+        gdb.handle(packet, computer, &Headless::new())
+    }
+
+    fn setup() -> (GdbServer, Computer) {
+        let mut computer = Computer::new(Platform::Chip8, Quirks::cosmac_vip());
+        computer.load_program(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        (GdbServer::bind(0).unwrap(), computer)
+    }
+
+    #[test]
+    fn reads_memory() {
+        let (mut gdb, mut computer) = setup();
+        assert_eq!(handle(&mut gdb, &mut computer, "m200,4").as_deref(), Some("602a1202"));
+        assert_eq!(handle(&mut gdb, &mut computer, "mfff,1").as_deref(), Some("00"));
+    }
+
+    #[test]
+    fn rejects_reads_past_the_end_of_memory() {
+        let (mut gdb, mut computer) = setup();
+        assert_eq!(handle(&mut gdb, &mut computer, "m0,ffffffffffffffff").as_deref(), Some("E01"));
+        assert_eq!(handle(&mut gdb, &mut computer, "mffffffffffffffff,2").as_deref(), Some("E01"));
+        assert_eq!(handle(&mut gdb, &mut computer, "mfff,2").as_deref(), Some("E01"));
+        assert_eq!(handle(&mut gdb, &mut computer, "m200").as_deref(), Some("E01"));
+    }
+
+    #[test]
+    fn writes_memory() {
+        let (mut gdb, mut computer) = setup();
+        assert_eq!(handle(&mut gdb, &mut computer, "M300,2:abcd").as_deref(), Some("OK"));
+        assert_eq!(computer.memory().read_u16(0x300).unwrap(), 0xABCD);
+    }
+
+    #[test]
+    fn rejects_bad_writes() {
+        let (mut gdb, mut computer) = setup();
+        assert_eq!(handle(&mut gdb, &mut computer, "Mffffffffffffffff,1:00").as_deref(), Some("E01"));
+        assert_eq!(handle(&mut gdb, &mut computer, "Mfff,2:0000").as_deref(), Some("E01"));
+        // the length doesn't match the data
+        assert_eq!(handle(&mut gdb, &mut computer, "M300,2:ab").as_deref(), Some("E01"));
+        assert_eq!(handle(&mut gdb, &mut computer, "M300,1:zz").as_deref(), Some("E01"));
+        assert_eq!(computer.memory().read_u8(0x300).unwrap(), 0);
+    }
+
+    #[test]
+    fn reads_all_registers() {
+        let (mut gdb, mut computer) = setup();
+        computer.set_register(0, 0x2A);
+        computer.set_index_register(0x123);
+        let registers = handle(&mut gdb, &mut computer, "g").unwrap();
+
+        // V0 to VF, then I and PC as little endian 16 bits, then SP, DT and ST
+        assert_eq!(registers.len(), 16 * 2 + 4 + 4 + 3 * 2);
+        assert_eq!(&registers[..2], "2a");
+        assert_eq!(&registers[32..36], "2301");
+        assert_eq!(&registers[36..40], "0002");
+    }
+
+    #[test]
+    fn adds_and_removes_breakpoints() {
+        let (mut gdb, mut computer) = setup();
+        assert_eq!(handle(&mut gdb, &mut computer, "Z0,202,2").as_deref(), Some("OK"));
+        // adding the same one again doesn't make a second
+        assert_eq!(handle(&mut gdb, &mut computer, "Z0,202,2").as_deref(), Some("OK"));
+        assert_eq!(gdb.debugger.breakpoints().len(), 1);
+        assert_eq!(gdb.debugger.breakpoints()[0].address, Some(0x202));
+
+        assert_eq!(handle(&mut gdb, &mut computer, "z0,202,2").as_deref(), Some("OK"));
+        assert!(gdb.debugger.breakpoints().is_empty());
+        // hardware breakpoints aren't supported
+        assert_eq!(handle(&mut gdb, &mut computer, "Z1,202,2").as_deref(), Some(""));
+        assert_eq!(handle(&mut gdb, &mut computer, "Z0,zz,2").as_deref(), Some("E01"));
+    }
+}
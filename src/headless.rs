@@ -0,0 +1,139 @@
+use crate::frontend::{AudioOutput, FrameSink, Keypad, Tone};
+
+/// A change in what the buzzer plays, frame counts the calls to `AudioOutput::play` before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ToneChange {
+    pub frame: usize,
+    pub tone: Option<Tone>,
+}
+
+/// An in-memory frontend for running ROMs without a window, keyboard or sound card.
+/// Keys are pressed programmatically, the last presented frame can be inspected and every change
+/// of the buzzer is recorded.
+pub struct Headless {
+    keys: [bool; 16],
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    frames_presented: usize,
+    tone: Option<Tone>,
+    audio_frames: usize,
+    active_audio_frames: usize,
+    tone_changes: Vec<ToneChange>,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self {
+            keys: [false; 16],
+            width: 0,
+            height: 0,
+            pixels: vec![],
+            frames_presented: 0,
+            tone: None,
+            audio_frames: 0,
+            active_audio_frames: 0,
+            tone_changes: vec![],
+        }
+    }
+
+    pub fn set_key(&mut self, key: u8, is_down: bool) {
+        self.keys[key as usize & 0xF] = is_down;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The pixels of the last presented frame, see `FrameSink`
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn frames_presented(&self) -> usize {
+        self.frames_presented
+    }
+
+    pub fn tone(&self) -> Option<Tone> {
+        self.tone
+    }
+
+    /// How many frames the buzzer was sounding for
+    pub fn active_audio_frames(&self) -> usize {
+        self.active_audio_frames
+    }
+
+    /// Every time the buzzer started, stopped or changed tone
+    pub fn tone_changes(&self) -> &[ToneChange] {
+        &self.tone_changes
+    }
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSink for Headless {
+    fn present(&mut self, width: usize, height: usize, pixels: &[u8]) {
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.extend_from_slice(pixels);
+        self.frames_presented += 1;
+    }
+}
+
+impl Keypad for Headless {
+    fn is_key_down(&self, key: u8) -> bool {
+        self.keys[key as usize & 0xF]
+    }
+}
+
+impl AudioOutput for Headless {
+    fn play(&mut self, tone: Option<Tone>) {
+        if tone != self.tone {
+            self.tone_changes.push(ToneChange { frame: self.audio_frames, tone });
+        }
+        if tone.is_some() {
+            self.active_audio_frames += 1;
+        }
+
+        self.tone = tone;
+        self.audio_frames += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, Platform, Quirks};
+
+    #[test]
+    fn runs_the_ibm_logo() {
+        let rom = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/IBM Logo.ch8"));
+        let mut computer = Computer::new(Platform::Chip8, Quirks::cosmac_vip());
+        computer.load_program(rom).unwrap();
+        let mut headless = Headless::new();
+        for _ in 0..60 {
+            computer.run_frame(&headless).unwrap();
+            computer.draw(&mut headless);
+        }
+
+        assert!(headless.frames_presented() > 0);
+        assert_eq!((headless.width(), headless.height()), (64, 32));
+        // the top left corner of the I, and the gap above it
+        assert_eq!(headless.pixel(12, 8), 1);
+        assert_eq!(headless.pixel(12, 7), 0);
+        assert_eq!(headless.pixels().iter().filter(|&&pixel| pixel != 0).count(), 208);
+    }
+}
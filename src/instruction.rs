@@ -10,18 +10,15 @@ impl Instruction {
     }
 
     pub fn op_code(&self) -> u8 {
-        let opcode = ((0xF000 & self.instruction) >> 12) as u8;
-        opcode
+        ((0xF000 & self.instruction) >> 12) as u8
     }
 
     pub fn x(&self) -> usize {
-        let xi = ((self.instruction & 0x0F00) >> 8) as usize;
-        xi
+        ((self.instruction & 0x0F00) >> 8) as usize
     }
 
     pub fn y(&self) -> usize {
-        let yi = ((self.instruction & 0x00F0) >> 4) as usize;
-        yi
+        ((self.instruction & 0x00F0) >> 4) as usize
     }
 
     pub fn xy(&self) -> [usize; 2] {
@@ -29,17 +26,14 @@ impl Instruction {
     }
 
     pub fn n(&self) -> u8 {
-        let byte = (self.instruction & 0xF) as u8;
-        byte
+        (self.instruction & 0xF) as u8
     }
 
     pub fn nn(&self) -> u8 {
-        let byte = (self.instruction & 0x00FF) as u8;
-        byte
+        (self.instruction & 0x00FF) as u8
     }
 
     pub fn nnn(&self) -> u16 {
         self.instruction & 0x0FFF
     }
 }
-
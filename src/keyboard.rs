@@ -2,6 +2,27 @@ use std::collections::HashMap;
 
 use sdl2::{event::Event, keyboard::Keycode};
 
+use rust_chip_8::Keypad;
+
+const KEY_MAP: [Keycode; 16] = [
+    Keycode::X, // 0
+    Keycode::Num1, // 1
+    Keycode::Num2, // 2
+    Keycode::Num3,  // 3
+    Keycode::Q, // 4
+    Keycode::W, // 5
+    Keycode::E, // 6
+    Keycode::A, // 7
+    Keycode::S, // 8
+    Keycode::D, // 9
+    Keycode::Z, // A
+    Keycode::C, // B
+    Keycode::Num4, // C
+    Keycode::R, // D
+    Keycode::F, // E
+    Keycode::V, // F
+];
+
 pub struct Keystate {
     pub is_down: bool,
     pub was_down: bool,
@@ -17,7 +38,7 @@ impl Keystate {
     }
 
     pub fn is_down(&self) -> bool {
-        return self.is_down;
+        self.is_down
     }
 }
 
@@ -30,30 +51,25 @@ impl Keyboard {
         Self { keystates: HashMap::new() }
     }
 
-    pub fn get_keystate(&mut self, keycode: Keycode) -> &Keystate {
-        // https://stackoverflow.com/questions/73801225/hashmap-get-or-insert-and-return-a-reference-from-a-function
-        self.keystates.entry(keycode).or_insert_with(|| Keystate::new().into())
+    pub fn get_keystate(&self, keycode: Keycode) -> Option<&Keystate> {
+        self.keystates.get(&keycode)
     }
 
     fn get_keystate_mut(&mut self, keycode: Keycode) -> &mut Keystate {
         // https://stackoverflow.com/questions/73801225/hashmap-get-or-insert-and-return-a-reference-from-a-function
-        self.keystates.entry(keycode).or_insert_with(|| Keystate::new().into())
+        self.keystates.entry(keycode).or_insert_with(Keystate::new)
     }
 
     pub fn process_event(&mut self, event: Event) {
         match event {
-            Event::KeyDown { keycode, .. } => {
-                if let Some(kc) = keycode {
-                    let keystate = self.get_keystate_mut(kc);
-                    keystate.is_down = true;
-                }
+            Event::KeyDown { keycode: Some(kc), .. } => {
+                let keystate = self.get_keystate_mut(kc);
+                keystate.is_down = true;
             },
 
-            Event::KeyUp { keycode, .. } => {
-                if let Some(kc) = keycode {
-                    let keystate = self.get_keystate_mut(kc);
-                    keystate.is_down = false;
-                }
+            Event::KeyUp { keycode: Some(kc), .. } => {
+                let keystate = self.get_keystate_mut(kc);
+                keystate.is_down = false;
             },
 
             _ => {}
@@ -65,4 +81,11 @@ impl Keyboard {
             keystate.update();
         }
     }
-}
\ No newline at end of file
+}
+
+impl Keypad for Keyboard {
+    fn is_key_down(&self, key: u8) -> bool {
+        let keycode = KEY_MAP[key as usize & 0xF];
+        self.get_keystate(keycode).is_some_and(|keystate| keystate.is_down())
+    }
+}
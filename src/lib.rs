@@ -0,0 +1,57 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP emulator core.
+//!
+//! Create a `Computer` for a `Platform`, load a ROM with `Computer::load_program`, then call
+//! `Computer::run_frame` 60 times a second (or `Computer::step` for a single instruction),
+//! passing something implementing `Keypad`. The screen is read back through `Computer::display`
+//! or pushed to a `FrameSink` with `Computer::draw`. `Headless` implements every frontend trait
+//! in memory for running ROMs without a window. `Disassembler` turns ROM bytes into a listing.
+
+mod computer;
+#[cfg(feature = "dap")]
+mod dap;
+mod debugger;
+mod disassembler;
+mod display;
+mod error;
+mod expression;
+mod frontend;
+mod gdb;
+mod headless;
+mod instruction;
+mod memory;
+mod movie;
+mod opcode;
+mod platform;
+mod quirks;
+mod rewind;
+mod rng;
+mod save_state;
+mod stack;
+mod timer;
+mod timing;
+mod trace;
+mod watchpoint;
+
+pub use computer::{Computer, MachineCallHook, MachineCallPolicy, DEFAULT_INSTRUCTIONS_PER_FRAME};
+#[cfg(feature = "dap")]
+pub use dap::{DapServer, LaunchArguments};
+pub use debugger::{current_op, describe_state, Breakpoint, Debugger, StopReason};
+pub use disassembler::{Disassembler, Syntax};
+pub use display::Display;
+pub use error::EmulatorError;
+pub use expression::{Expression, ParseError};
+pub use frontend::{AudioOutput, FrameSink, Keypad, Tone};
+pub use gdb::GdbServer;
+pub use headless::{Headless, ToneChange};
+pub use instruction::Instruction;
+pub use memory::Memory;
+pub use movie::{Movie, MovieError, MovieFrame};
+pub use opcode::Op;
+pub use platform::Platform;
+pub use quirks::{IndexIncrement, Quirks, QuirksPreset};
+pub use rewind::Rewind;
+pub use rng::{RngMode, VIP_INTERPRETER_SIZE};
+pub use stack::{Stack, SCHIP_STACK_DEPTH, VIP_STACK_ADDRESS, VIP_STACK_DEPTH};
+pub use timing::Timing;
+pub use trace::Trace;
+pub use watchpoint::{Access, WatchAction, WatchHit, Watchpoint};
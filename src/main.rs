@@ -1,38 +1,212 @@
 mod sdl_system;
-mod computer;
-mod memory;
-mod display;
-mod stack;
-mod instruction;
 mod keyboard;
-mod timer;
+mod audio;
+mod debug_console;
+mod window_geometry;
 
-use std::{path::Path, time::Duration};
-use clap::Parser;
+use std::{path::Path, time::{Duration, Instant}};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
-use computer::Computer;
+use audio::{AudioSettings, Waveform};
+use debug_console::DebugConsole;
 use keyboard::Keyboard;
-use sdl2::{event::Event, keyboard::Keycode};
+use rust_chip_8::{Breakpoint, Computer, Debugger, Disassembler, EmulatorError, GdbServer, Headless, Keypad, MachineCallPolicy, Movie, Platform, Quirks, QuirksPreset, Rewind, RngMode, Syntax, Timing, Trace, DEFAULT_INSTRUCTIONS_PER_FRAME};
+#[cfg(feature = "dap")]
+use rust_chip_8::DapServer;
+use sdl2::{event::Event, keyboard::{Keycode, Mod}};
+use window_geometry::WindowGeometry;
+use sdl2::messagebox::{show_simple_message_box, MessageBoxFlag};
 
 use crate::sdl_system::SdlSystem;
 
 /// Chip-8 Emulator written in rust
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print an assembly listing of a ROM
+    Disasm(DisasmArgs),
+    /// Serve the Debug Adapter Protocol on stdin and stdout, the client launches the ROM
+    #[cfg(feature = "dap")]
+    Dap,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
     /// The path to the ROM file to read
     path: std::path::PathBuf,
+
+    /// The CHIP-8 variant the ROM was written for
+    #[arg(short, long, value_enum, default_value_t = Platform::Chip8)]
+    platform: Platform,
+
+    /// The assembly language to write
+    #[arg(short, long, value_enum, default_value_t = Syntax::Classic)]
+    syntax: Syntax,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// The path to the ROM file to read
+    #[arg(required_unless_present = "state")]
+    path: Option<std::path::PathBuf>,
+
+    /// Boot from a save state instead of a ROM, the state decides platform, quirks and speed
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["platform", "quirks", "instructions_per_frame", "timing", "stack_depth", "stack_in_memory"])]
+    state: Option<std::path::PathBuf>,
+
+    /// The CHIP-8 variant to emulate
+    #[arg(short, long, value_enum, default_value_t = Platform::Chip8)]
+    platform: Platform,
+
+    /// The interpreter whose quirks the ambiguous instructions should follow, defaults to the platform's own
+    #[arg(short, long, value_enum)]
+    quirks: Option<QuirksPreset>,
+
+    /// CPU speed in instructions executed per 60Hz frame, change it while running with - and =
+    #[arg(long = "ipf", value_name = "INSTRUCTIONS", default_value_t = DEFAULT_INSTRUCTIONS_PER_FRAME)]
+    instructions_per_frame: u32,
+
+    /// How long instructions take: instructions_per_frame each frame, or as many machine cycles as on the COSMAC VIP
+    #[arg(long, value_enum, default_value_t = Timing::Instructions)]
+    timing: Timing,
+
+    /// How deep subroutine calls can nest, 12 for CHIP-8 as on the COSMAC VIP, 16 for SUPER-CHIP and XO-CHIP
+    #[arg(long, value_name = "CALLS")]
+    stack_depth: Option<usize>,
+
+    /// Keep the call stack in RAM at 0xEA0-0xECF like the COSMAC VIP, for ROMs that read or change it
+    #[arg(long)]
+    stack_in_memory: bool,
+
+    /// Stop with an error when the program goes outside memory or writes below 0x200, instead of wrapping around
+    #[arg(long)]
+    strict_memory: bool,
+
+    /// Seed for the CXNN random numbers, so a run can be repeated. Random by default
+    #[arg(long, value_parser = parse_seed, conflicts_with_all = ["state", "play"])]
+    seed: Option<u64>,
+
+    /// How CXNN makes random numbers: SplitMix64, or the COSMAC VIP interpreter's routine
+    #[arg(long = "rng", value_name = "ALGORITHM", value_enum, default_value_t = RngMode::SplitMix64, conflicts_with_all = ["state", "play"])]
+    rng_mode: RngMode,
+
+    /// Dump of the COSMAC VIP's 512 byte CHIP-8 interpreter, which --rng vip makes its numbers from
+    #[arg(long, value_name = "FILE", required_if_eq("rng_mode", "vip"), conflicts_with_all = ["state", "play"])]
+    vip_interpreter: Option<std::path::PathBuf>,
+
+    /// What to do with 0NNN calls to COSMAC VIP machine code
+    #[arg(long, value_enum, default_value_t = MachineCalls::Ignore)]
+    machine_calls: MachineCalls,
+
+    /// Scale the screen by whole numbers only, so every pixel is the same size
+    #[arg(long)]
+    integer_scaling: bool,
+
+    /// Frequency of the beep in Hz
+    #[arg(long, default_value_t = 440.0)]
+    tone_frequency: f32,
+
+    /// Shape of the beep
+    #[arg(long, value_enum, default_value_t = Waveform::Square)]
+    waveform: Waveform,
+
+    /// Volume from 0.0 to 1.0, mute while running with M
+    #[arg(long, default_value_t = 0.25)]
+    volume: f32,
+
+    /// Memory for the rewind buffer in MiB, hold Backspace to rewind. 0 turns rewinding off
+    #[arg(long, value_name = "MIB", default_value_t = 32)]
+    rewind_memory: usize,
+
+    /// Record the keypad to a movie file that replays this session exactly
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["state", "debug", "breakpoints", "gdb"])]
+    record: Option<std::path::PathBuf>,
+
+    /// Play back a movie, stopping with an error at the first frame that turns out differently
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["state", "debug", "breakpoints", "gdb", "record", "platform", "quirks", "instructions_per_frame", "timing", "stack_depth", "stack_in_memory", "strict_memory", "machine_calls"])]
+    play: Option<std::path::PathBuf>,
+
+    /// Run for this many frames without opening a window, then print the screen
+    #[arg(long, value_name = "FRAMES")]
+    headless: Option<usize>,
+
+    /// Write a line to this file for every instruction executed: instruction count, PC, opcode, instruction, then registers, I and timers
+    #[arg(long, value_name = "FILE")]
+    trace: Option<std::path::PathBuf>,
+
+    /// Start paused with a debugger reading commands from the terminal
+    #[arg(long, conflicts_with = "headless")]
+    debug: bool,
+
+    /// Run with the debugger until the breakpoint is hit, e.g. "0x2A4 if V3 == 0x10 after 2", can be repeated
+    #[arg(long = "break", value_name = "BREAKPOINT", value_parser = Breakpoint::parse, conflicts_with = "headless")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Start paused and wait for a GDB remote protocol client on this localhost port
+    #[arg(long, value_name = "PORT", conflicts_with_all = ["headless", "debug", "breakpoints"])]
+    gdb: Option<u16>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MachineCalls {
+    /// Skip them, like most interpreters after the VIP
+    Ignore,
+    /// Stop with an error, to find out if a ROM needs them
+    Error,
 }
 
+/// A movie being recorded or played back
+enum MovieMode {
+    Record(Movie),
+    /// The next frame to play
+    Play { movie: Movie, frame: usize },
+}
+
+/// Seeds are decimal, or hex with 0x
+fn parse_seed(text: &str) -> Result<u64, String> {
+    let seed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    seed.map_err(|error| error.to_string())
+}
+
+/// Who is in control when the program is being debugged
+enum DebugFrontend {
+    Console(DebugConsole),
+    Gdb(GdbServer),
+    #[cfg(feature = "dap")]
+    Dap(Box<DapServer>),
+}
+
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+const SLOT_KEYS: [Keycode; 9] = [
+    Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5,
+    Keycode::F6, Keycode::F7, Keycode::F8, Keycode::F9,
+];
+
 pub struct Context<'a> {
     pub sdl: &'a mut SdlSystem,
 }
 
-pub fn run(sdl: &mut SdlSystem, computer: &mut Computer) -> Result<(), String> {
+/// Save states for slot_base (the ROM) go beside it, e.g. roms/game.state1 for slot 1
+fn run(sdl: &mut SdlSystem, computer: &mut Computer, slot_base: &Path, mut rewind: Option<Rewind>, movie: &mut Option<MovieMode>, mut debug_frontend: Option<DebugFrontend>) -> Result<(), String> {
     let mut event_pump = sdl.sdl_context.event_pump()?;
     let mut keyboard = Keyboard::new();
 
     'running: loop {
+        let frame_start = Instant::now();
+
         {
             //let mut context = Context{ sdl };
             
@@ -43,6 +217,41 @@ pub fn run(sdl: &mut SdlSystem, computer: &mut Computer) -> Result<(), String> {
                     Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                         break 'running;
                     },
+                    // the movie holds one speed, and the state hashes cover it
+                    Event::KeyDown { keycode: Some(Keycode::Equals | Keycode::Minus), .. } if movie.is_some() => {
+                        eprintln!("Can't change the speed while a movie is recording or playing");
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::Equals), .. } => {
+                        change_speed(computer, 1);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::Minus), .. } => {
+                        change_speed(computer, -1);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+                        if let Err(error) = sdl.toggle_fullscreen() {
+                            eprintln!("Could not change to or from fullscreen: {}", error);
+                        }
+                    },
+                    Event::Window { ref win_event, .. } => {
+                        sdl.handle_window_event(win_event);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                        let muted = sdl.toggle_mute();
+                        eprintln!("Sound {}", if muted { "muted" } else { "unmuted" });
+                    },
+                    Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if SLOT_KEYS.contains(&keycode) => {
+                        let slot = SLOT_KEYS.iter().position(|&key| key == keycode).unwrap() + 1;
+                        let path = slot_base.with_extension(format!("state{}", slot));
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            if movie.is_some() {
+                                eprintln!("Can't load a state while a movie is recording or playing");
+                                continue;
+                            }
+                            load_state(computer, &path, slot);
+                        } else {
+                            save_state(computer, &path, slot);
+                        }
+                    },
                     _ => {}
                 }
 
@@ -55,26 +264,297 @@ pub fn run(sdl: &mut SdlSystem, computer: &mut Computer) -> Result<(), String> {
 
         {
             //let mut context = Context{ sdl };
-            let dt = 0.0167f32;
             keyboard.update();
             //let current_scene = &mut self.scenes[self.current_scene_idx];
             //current_scene.update(&mut context);
             //current_scene.draw(&mut context);
-            computer.update(dt, &mut keyboard);
+            let rewinding = rewind.is_some() && keyboard.get_keystate(Keycode::Backspace).is_some_and(|key| key.is_down());
+            if rewinding {
+                if let Some(state) = rewind.as_mut().and_then(|rewind| rewind.step_back()) {
+                    computer.load_state(state).map_err(|error| error.to_string())?;
+                }
+            } else if let Some(frontend) = debug_frontend.as_mut() {
+                match frontend {
+                    DebugFrontend::Console(console) => {
+                        if !console.run_frame(computer, &keyboard) {
+                            break 'running;
+                        }
+                    }
+                    DebugFrontend::Gdb(gdb) => gdb.run_frame(computer, &keyboard),
+                    #[cfg(feature = "dap")]
+                    DebugFrontend::Dap(dap) => {
+                        if !dap.run_frame(computer, &keyboard) {
+                            break 'running;
+                        }
+                    }
+                }
+            } else if let Err(message) = run_frame(computer, &keyboard, movie) {
+                computer.draw(sdl);
+                show_simple_message_box(MessageBoxFlag::ERROR, "Rust Chip-8", &message, sdl.canvas.window())
+                    .map_err(|e| e.to_string())?;
+                return Err(message);
+            }
+            if let Some(rewind) = rewind.as_mut().filter(|_| !rewinding) {
+                rewind.push(computer.save_state());
+            }
             computer.draw(sdl);
+            computer.play_audio(sdl);
+
+            if computer.has_exited() {
+                break 'running;
+            }
         }
 
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        ::std::thread::sleep(FRAME_DURATION.saturating_sub(frame_start.elapsed()));
     }
 
     Ok(())
 }
 
+/// Emulate one frame, recording it or playing it back if there is a movie. Once a movie has
+/// played to the end the keypad takes over
+fn run_frame(computer: &mut Computer, keypad: &dyn Keypad, movie: &mut Option<MovieMode>) -> Result<(), String> {
+    let describe = |error: EmulatorError, computer: &Computer| format!("{} (PC {:#05X})", error, computer.program_counter());
+    match movie {
+        None => computer.run_frame(keypad).map_err(|error| describe(error, computer)),
+        Some(MovieMode::Record(recording)) => recording.record_frame(computer, keypad).map_err(|error| describe(error, computer)),
+        Some(MovieMode::Play { movie: recorded, frame }) => {
+            if !recorded.play_frame(*frame, computer).map_err(|error| describe(error, computer))? {
+                return Err(format!("Movie desynced at frame {}, the machine state differs from the recording", frame));
+            }
+            *frame += 1;
+            if *frame >= recorded.frames().len() {
+                eprintln!("Movie finished, all {} frames matched", frame);
+                *movie = None;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn save_state(computer: &Computer, path: &Path, slot: usize) {
+    match std::fs::write(path, computer.save_state()) {
+        Ok(()) => eprintln!("Saved slot {} to {}", slot, path.display()),
+        Err(error) => eprintln!("Could not save slot {} to {}: {}", slot, path.display(), error),
+    }
+}
+
+fn load_state(computer: &mut Computer, path: &Path, slot: usize) {
+    let result = std::fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| computer.load_state(&data).map_err(|error| error.to_string()));
+    match result {
+        Ok(()) => eprintln!("Loaded slot {} from {}", slot, path.display()),
+        Err(error) => eprintln!("Could not load slot {} from {}: {}", slot, path.display(), error),
+    }
+}
+
+fn change_speed(computer: &mut Computer, delta: i32) {
+    if computer.timing() == Timing::CosmacVip {
+        eprintln!("Speed: set by the COSMAC VIP timing");
+        return;
+    }
+    let instructions_per_frame = computer.instructions_per_frame().saturating_add_signed(delta);
+    computer.set_instructions_per_frame(instructions_per_frame);
+    eprintln!("Speed: {} instructions per frame ({} Hz)", computer.instructions_per_frame(), computer.instructions_per_frame() * 60);
+}
+
+fn run_headless(headless: &mut Headless, computer: &mut Computer, movie: &mut Option<MovieMode>, frames: usize) -> Result<(), String> {
+    let mut result = Ok(());
+    for _ in 0..frames {
+        result = run_frame(computer, headless, movie);
+        computer.draw(headless);
+        computer.play_audio(headless);
+
+        if result.is_err() || computer.has_exited() {
+            break;
+        }
+    }
+
+    for y in 0..headless.height() {
+        let row: String = (0..headless.width())
+            .map(|x| if headless.pixel(x, y) == 0 { '.' } else { '#' })
+            .collect();
+        println!("{}", row);
+    }
+    println!("Buzzer active for {} frames", headless.active_audio_frames());
+
+    result
+}
+
+fn disassemble(args: DisasmArgs) -> Result<(), String> {
+    let rom = std::fs::read(&args.path)
+        .map_err(|error| format!("Could not load {}: {}", args.path.display(), error))?;
+    let disassembler = Disassembler::new(&rom, args.platform);
+    print!("{}", disassembler.listing(args.syntax));
+    Ok(())
+}
+
+/// The window opens where it was when the emulator last closed
+fn open_window(audio_settings: AudioSettings, integer_scaling: bool) -> SdlSystem {
+    let mut sdl = SdlSystem::new("Rust Chip-8", WindowGeometry::load(), integer_scaling);
+    if let Err(error) = sdl.open_audio(audio_settings) {
+        eprintln!("No sound, could not open an audio device: {}", error);
+    }
+    sdl
+}
+
+fn close_window(sdl: &SdlSystem) {
+    if let Err(error) = sdl.geometry().save() {
+        eprintln!("Could not save the window position: {}", error);
+    }
+}
+
+/// Nothing else may write to stdout while serving, it carries the protocol
+#[cfg(feature = "dap")]
+fn serve_dap() -> Result<(), String> {
+    let mut dap = DapServer::stdio();
+    let Some(mut computer) = dap.launch() else {
+        return Ok(());
+    };
+
+    let mut sdl = open_window(AudioSettings {
+        frequency: 440.0,
+        waveform: Waveform::Square,
+        volume: 0.25,
+    }, false);
+    let slot_base: std::path::PathBuf = dap.launch_arguments().map(|launch| launch.program.clone()).unwrap_or_default();
+    let result = run(&mut sdl, &mut computer, &slot_base, None, &mut None, Some(DebugFrontend::Dap(Box::new(dap))));
+    close_window(&sdl);
+    result
+}
+
 fn main() -> Result<(), String> {
-    let args = Args::parse();
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Disasm(args)) => return disassemble(args),
+        #[cfg(feature = "dap")]
+        Some(Command::Dap) => return serve_dap(),
+        None => {}
+    }
+
+    let args = cli.run;
+    let vip_interpreter = match &args.vip_interpreter {
+        Some(path) => Some(std::fs::read(path).map_err(|error| format!("Could not load {}: {}", path.display(), error))?),
+        None => None,
+    };
+    let mut movie_mode = None;
+    let (mut computer, slot_base) = match (args.path, args.state) {
+        (path, Some(state_path)) => {
+            let computer = std::fs::read(&state_path)
+                .map_err(|error| error.to_string())
+                .and_then(|data| Computer::from_state(&data).map_err(|error| error.to_string()))
+                .map_err(|error| format!("Could not load {}: {}", state_path.display(), error))?;
+            (computer, path.unwrap_or(state_path))
+        }
+        (path, None) => {
+            let path = path.expect("path is required");
+            let rom = std::fs::read(&path)
+                .map_err(|error| format!("Could not load {}: {}", path.display(), error))?;
+            let quirks = match args.quirks {
+                Some(preset) => Quirks::from_preset(preset),
+                None => args.platform.default_quirks(),
+            };
+
+            if let Some(play_path) = &args.play {
+                let movie = std::fs::read_to_string(play_path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| Movie::parse(&text).map_err(|error| error.to_string()))
+                    .map_err(|error| format!("Could not load {}: {}", play_path.display(), error))?;
+                let computer = movie.start(&rom).map_err(|error| error.to_string())?;
+                movie_mode = Some(MovieMode::Play { movie, frame: 0 });
+                (computer, path)
+            } else if args.record.is_some() {
+                let seed = args.seed.unwrap_or_else(rand::random);
+                let mut movie = Movie::new(&rom, args.platform, quirks, args.instructions_per_frame, args.timing, seed);
+                movie.stack_depth = args.stack_depth.unwrap_or(movie.stack_depth);
+                movie.stack_in_memory = args.stack_in_memory;
+                movie.strict_memory = args.strict_memory;
+                movie.rng_mode = args.rng_mode;
+                movie.vip_interpreter = vip_interpreter;
+                movie.machine_call_errors = matches!(args.machine_calls, MachineCalls::Error);
+                let computer = movie.start(&rom).map_err(|error| error.to_string())?;
+                movie_mode = Some(MovieMode::Record(movie));
+                (computer, path)
+            } else {
+                let mut computer = Computer::new(args.platform, quirks);
+                computer.set_instructions_per_frame(args.instructions_per_frame);
+                computer.set_timing(args.timing);
+                if let Some(depth) = args.stack_depth {
+                    computer.set_stack_depth(depth);
+                }
+                computer.set_stack_in_memory(args.stack_in_memory);
+                computer.set_rng_mode(args.rng_mode);
+                if let Some(interpreter) = &vip_interpreter {
+                    computer.set_vip_interpreter(interpreter).map_err(|error| error.to_string())?;
+                }
+                if let Some(seed) = args.seed {
+                    computer.set_random_seed(seed);
+                }
+                computer.load_program(&rom)
+                    .map_err(|error| format!("Could not load {}: {}", path.display(), error))?;
+                (computer, path)
+            }
+        }
+    };
+    if let Some(trace_path) = args.trace {
+        let trace = Trace::to_file(&trace_path)
+            .map_err(|error| format!("Could not create {}: {}", trace_path.display(), error))?;
+        computer.set_trace(Some(trace));
+    }
+    // a movie sets these itself
+    if movie_mode.is_none() {
+        computer.set_strict_memory(args.strict_memory);
+        if let MachineCalls::Error = args.machine_calls {
+            computer.set_machine_call_policy(MachineCallPolicy::Error);
+        }
+    }
+
+    if let Some(frames) = args.headless {
+        let result = run_headless(&mut Headless::new(), &mut computer, &mut movie_mode, frames);
+        return save_movie(args.record.as_deref(), movie_mode).and(result);
+    }
+
+    let mut sdl = open_window(AudioSettings {
+        frequency: args.tone_frequency,
+        waveform: args.waveform,
+        volume: args.volume.clamp(0.0, 1.0),
+    }, args.integer_scaling);
+    let debug_frontend = if let Some(port) = args.gdb {
+        let gdb = GdbServer::bind(port)
+            .map_err(|error| format!("Could not listen on port {}: {}", port, error))?;
+        println!("Waiting for a GDB client on localhost:{}", port);
+        Some(DebugFrontend::Gdb(gdb))
+    } else if args.debug || !args.breakpoints.is_empty() {
+        let mut debugger = Debugger::new();
+        for breakpoint in args.breakpoints {
+            debugger.add_breakpoint(breakpoint);
+        }
+        if !args.debug {
+            debugger.resume();
+        }
 
-    let mut sdl = SdlSystem::new("Rust Chip-8", 640, 320);
-    let mut computer = Computer::new();
-    computer.load_program_from_file(Path::new(&args.path));
-    run(&mut sdl, &mut computer)
+        let console = DebugConsole::new(debugger);
+        if args.debug {
+            console.show_state(&computer);
+        }
+        Some(DebugFrontend::Console(console))
+    } else {
+        None
+    };
+    // rewinding would take the recording back in time with it
+    let rewind = (args.rewind_memory > 0 && movie_mode.is_none()).then(|| Rewind::new(args.rewind_memory * 1024 * 1024));
+    let result = run(&mut sdl, &mut computer, &slot_base, rewind, &mut movie_mode, debug_frontend);
+    close_window(&sdl);
+    save_movie(args.record.as_deref(), movie_mode).and(result)
+}
+
+/// Write the movie if one was being recorded, even when the session ended with an error
+fn save_movie(path: Option<&Path>, movie_mode: Option<MovieMode>) -> Result<(), String> {
+    if let (Some(path), Some(MovieMode::Record(movie))) = (path, movie_mode) {
+        std::fs::write(path, movie.to_string())
+            .map_err(|error| format!("Could not save {}: {}", path.display(), error))?;
+        eprintln!("Recorded {} frames to {}", movie.frames().len(), path.display());
+    }
+    Ok(())
 }
@@ -1,40 +1,133 @@
+use crate::error::EmulatorError;
+use crate::save_state::{invalid, StateReader, StateWriter};
 
+/// Where the interpreter lived on the COSMAC VIP, and here the fonts, which programs start after
+const INTERPRETER_END: usize = 0x200;
+
+/// The machine's RAM.
+///
+/// The plain reads and writes are for frontends and debuggers and fail outside memory. The
+/// program's own accesses go through `program_address` first, which wraps around the end of
+/// memory as the hardware does. In strict mode it reports them instead, along with writes over
+/// the interpreter and fonts, to help find bugs in ROMs.
 pub struct Memory {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
+    strict: bool,
 }
 
 impl Memory {
-    pub fn new() -> Self {
-        let memory = [0; 4096];
+    /// Create zeroed memory of the given size: 4 KiB for CHIP-8 and SUPER-CHIP, 64 KiB for XO-CHIP
+    pub fn new(size: usize) -> Self {
+        let memory = vec![0; size];
         Self {
             memory,
+            strict: false,
         }
     }
 
-    pub fn load(&mut self, start_address: usize, data: &[u8]) {
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Where the program reading or writing address really goes. Addresses past the end wrap
+    /// around to the start, or in strict mode are an error, as is writing below 0x200
+    pub fn program_address(&self, address: usize, write: bool) -> Result<usize, EmulatorError> {
+        if !self.strict {
+            return Ok(address % self.memory.len());
+        }
+
+        self.check_range(address, 1)?;
+        if write && address < INTERPRETER_END {
+            return Err(EmulatorError::ProtectedWrite { address });
+        }
+        Ok(address)
+    }
+
+    pub fn load(&mut self, start_address: usize, data: &[u8]) -> Result<(), EmulatorError> {
         // https://stackoverflow.com/questions/25225346/how-do-you-copy-between-arrays-of-different-sizes-in-rust
-        let end_address = start_address + data.len();
-        self.memory[start_address..end_address].copy_from_slice(&data);
+        let end_address = self.check_range(start_address, data.len())?;
+        self.memory[start_address..end_address].copy_from_slice(data);
+        Ok(())
     }
 
-    pub fn read_u8_array(&self, address: usize, output: &mut [u8]) {
-        let end_address = address + output.len();
+    pub fn read_u8_array(&self, address: usize, output: &mut [u8]) -> Result<(), EmulatorError> {
+        let end_address = self.check_range(address, output.len())?;
         output.copy_from_slice(&self.memory[address..end_address]);
+        Ok(())
     }
 
-    pub fn read_u8(&self, address: usize) -> u8 {
+    pub fn read_u8(&self, address: usize) -> Result<u8, EmulatorError> {
         let mut bytes = [0; 1];
-        self.read_u8_array(address, &mut bytes);
-        bytes[0]
+        self.read_u8_array(address, &mut bytes)?;
+        Ok(bytes[0])
     }
 
-    pub fn read_u16(&self, address: usize) -> u16 {
+    pub fn read_u16(&self, address: usize) -> Result<u16, EmulatorError> {
         let mut bytes = [0; 2];
-        self.read_u8_array(address, &mut bytes);
-        u16::from_be_bytes(bytes)
+        self.read_u8_array(address, &mut bytes)?;
+        Ok(u16::from_be_bytes(bytes))
     }
 
-    pub fn write_u8(&mut self, address: usize, value: u8) {
+    pub fn write_u8(&mut self, address: usize, value: u8) -> Result<(), EmulatorError> {
+        self.check_range(address, 1)?;
         self.memory[address] = value;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.memory);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmulatorError> {
+        let memory = state.bytes()?;
+        if memory.len() != self.memory.len() {
+            return Err(invalid(format!("{} bytes of memory, the platform has {}", memory.len(), self.memory.len())));
+        }
+        self.memory.copy_from_slice(memory);
+        Ok(())
+    }
+
+    /// The end of length bytes from start_address, or an error with the first out of range
+    /// address if any of them is outside memory
+    fn check_range(&self, start_address: usize, length: usize) -> Result<usize, EmulatorError> {
+        match start_address.checked_add(length) {
+            Some(end_address) if end_address <= self.memory.len() => Ok(end_address),
+            _ => Err(EmulatorError::MemoryOutOfRange { address: start_address.max(self.memory.len()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_past_the_end_of_the_address_space_are_out_of_range() {
+        let mut memory = Memory::new(0x1000);
+        assert!(matches!(memory.load(usize::MAX, &[0]), Err(EmulatorError::MemoryOutOfRange { address: usize::MAX })));
+        let mut bytes = [0; 2];
+        assert!(matches!(memory.read_u8_array(usize::MAX, &mut bytes), Err(EmulatorError::MemoryOutOfRange { address: usize::MAX })));
+        assert!(matches!(memory.read_u8_array(0xFFF, &mut bytes), Err(EmulatorError::MemoryOutOfRange { address: 0x1000 })));
+    }
+
+    #[test]
+    fn program_addresses_wrap_unless_strict() {
+        let mut memory = Memory::new(0x1000);
+        assert_eq!(memory.program_address(0x1001, false).unwrap(), 0x001);
+        assert_eq!(memory.program_address(0x100, true).unwrap(), 0x100);
+
+        memory.set_strict(true);
+        assert_eq!(memory.program_address(0xFFF, true).unwrap(), 0xFFF);
+        assert!(matches!(memory.program_address(0x1001, false), Err(EmulatorError::MemoryOutOfRange { address: 0x1001 })));
+        assert_eq!(memory.program_address(0x100, false).unwrap(), 0x100);
+        assert!(matches!(memory.program_address(0x1FF, true), Err(EmulatorError::ProtectedWrite { address: 0x1FF })));
+    }
+}
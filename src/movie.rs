@@ -0,0 +1,396 @@
+use std::fmt;
+
+use crate::computer::{Computer, MachineCallPolicy};
+use crate::error::EmulatorError;
+use crate::frontend::Keypad;
+use crate::platform::Platform;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{RngMode, VIP_INTERPRETER_SIZE};
+use crate::timing::Timing;
+
+const HEADER: &str = "rust-chip-8 movie 7";
+
+/// Why a movie can't be read or played
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The movie file is malformed, line counts from 1
+    Parse { line: usize, message: String },
+    /// The movie was recorded with a different ROM
+    RomMismatch { expected: u64, found: u64 },
+    /// The COSMAC VIP interpreter for the random numbers isn't 512 bytes
+    BadInterpreter { size: usize },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "{} on line {}", message, line),
+            MovieError::RomMismatch { expected, found } => write!(f, "movie was recorded with ROM {:016x}, this ROM is {:016x}", expected, found),
+            MovieError::BadInterpreter { size } => write!(f, "COSMAC VIP interpreter is {} bytes, it should be 512", size),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// One frame of a movie: the keys held, and a hash of the machine state after the frame ran
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// Bit N is set while key N is down
+    pub keys: u16,
+    pub state_hash: u64,
+}
+
+/// A recording of a session that replays exactly: the ROM, the settings and random seed it ran
+/// with, and the keypad for every frame. Each frame also has a hash of the machine state after
+/// it, so playback notices the first frame that turns out differently.
+///
+/// Movies are text, so they can go in bug reports and under version control:
+///
+/// ```text
+/// rust-chip-8 movie 7
+/// rom 8d6a1c2bd13a5f2e
+/// platform chip8
+/// quirks shift_uses_vy load_store_increment_index vf_reset display_wait
+/// ipf 11
+/// timing instructions
+/// stack 12
+/// memory wrap
+/// machine_calls ignore
+/// rng splitmix64
+/// seed 3f2b99c01d7e4a65
+/// frames
+/// 0000 0c1b7e0d5b2a9f31
+/// 0020 5e33a8b09c71d244
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// FNV-1a of the ROM
+    pub rom_hash: u64,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub timing: Timing,
+    /// The platform's stack depth unless changed after `Movie::new`
+    pub stack_depth: usize,
+    pub stack_in_memory: bool,
+    /// Going outside memory or writing below 0x200 is an error instead of wrapping around
+    pub strict_memory: bool,
+    /// 0NNN machine calls are an error instead of being skipped. Hooks can't be recorded
+    pub machine_call_errors: bool,
+    pub rng_mode: RngMode,
+    /// The COSMAC VIP interpreter `RngMode::Vip` makes its numbers from, written out in hex
+    /// after the mode so the movie plays without it
+    pub vip_interpreter: Option<Vec<u8>>,
+    pub seed: u64,
+    frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// An empty movie for recording
+    pub fn new(rom: &[u8], platform: Platform, quirks: Quirks, instructions_per_frame: u32, timing: Timing, seed: u64) -> Self {
+        Self {
+            rom_hash: fnv1a(rom),
+            platform,
+            quirks,
+            instructions_per_frame,
+            timing,
+            stack_depth: platform.stack_depth(),
+            stack_in_memory: false,
+            strict_memory: false,
+            machine_call_errors: false,
+            rng_mode: RngMode::default(),
+            vip_interpreter: None,
+            seed,
+            frames: vec![],
+        }
+    }
+
+    /// A computer with the movie's settings and seed, with the ROM loaded, ready to record or play
+    pub fn start(&self, rom: &[u8]) -> Result<Computer, MovieError> {
+        let found = fnv1a(rom);
+        if found != self.rom_hash {
+            return Err(MovieError::RomMismatch { expected: self.rom_hash, found });
+        }
+
+        let mut computer = Computer::new(self.platform, self.quirks);
+        computer.set_instructions_per_frame(self.instructions_per_frame);
+        computer.set_timing(self.timing);
+        computer.set_stack_depth(self.stack_depth);
+        computer.set_stack_in_memory(self.stack_in_memory);
+        computer.set_strict_memory(self.strict_memory);
+        if self.machine_call_errors {
+            computer.set_machine_call_policy(MachineCallPolicy::Error);
+        }
+        computer.set_rng_mode(self.rng_mode);
+        if let Some(interpreter) = &self.vip_interpreter {
+            computer.set_vip_interpreter(interpreter).map_err(|_| MovieError::BadInterpreter { size: interpreter.len() })?;
+        }
+        computer.set_random_seed(self.seed);
+        // start() already checked the ROM is the one recorded, which loaded fine then
+        computer.load_program(rom).ok();
+        Ok(computer)
+    }
+
+    pub fn frames(&self) -> &[MovieFrame] {
+        &self.frames
+    }
+
+    /// Run one frame with the keys held on keypad, and add it to the movie
+    pub fn record_frame(&mut self, computer: &mut Computer, keypad: &dyn Keypad) -> Result<(), EmulatorError> {
+        let keys = (0..16).filter(|&key| keypad.is_key_down(key)).fold(0, |keys, key| keys | 1 << key);
+        computer.run_frame(&Keys(keys))?;
+        self.frames.push(MovieFrame { keys, state_hash: fnv1a(&computer.save_state()) });
+        Ok(())
+    }
+
+    /// Run frame number frame with the keys recorded for it. Returns false if the machine state
+    /// after it differs from the recording. Past the end of the movie no keys are held
+    pub fn play_frame(&self, frame: usize, computer: &mut Computer) -> Result<bool, EmulatorError> {
+        let Some(recorded) = self.frames.get(frame) else {
+            computer.run_frame(&Keys(0))?;
+            return Ok(true);
+        };
+
+        computer.run_frame(&Keys(recorded.keys))?;
+        Ok(fnv1a(&computer.save_state()) == recorded.state_hash)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+        let mut field = |name: &str| -> Result<(usize, &str), MovieError> {
+            let (line, text) = lines.next().unwrap_or((0, ""));
+            let value = text.strip_prefix(name).filter(|value| value.is_empty() || value.starts_with(' '));
+            value.map(|value| (line, value.trim())).ok_or(MovieError::Parse { line, message: format!("expected {}", name) })
+        };
+
+        field(HEADER)?;
+        let (line, rom) = field("rom")?;
+        let rom_hash = parse_hex(rom, line)?;
+        let (line, platform) = field("platform")?;
+        let platform = Platform::from_name(platform).ok_or_else(|| MovieError::Parse { line, message: format!("unknown platform {}", platform) })?;
+        let (line, quirk_names) = field("quirks")?;
+        let quirks = parse_quirks(quirk_names, line)?;
+        let (line, ipf) = field("ipf")?;
+        let instructions_per_frame = ipf.parse().map_err(|_| MovieError::Parse { line, message: format!("bad speed {}", ipf) })?;
+        let (line, timing) = field("timing")?;
+        let timing = Timing::from_name(timing).ok_or_else(|| MovieError::Parse { line, message: format!("unknown timing {}", timing) })?;
+        let (line, stack) = field("stack")?;
+        let (stack_depth, stack_in_memory) = match stack.split_once(' ') {
+            Some((depth, "memory")) => (depth, true),
+            None => (stack, false),
+            Some(_) => return Err(MovieError::Parse { line, message: format!("bad stack {}", stack) }),
+        };
+        let stack_depth = stack_depth.parse().map_err(|_| MovieError::Parse { line, message: format!("bad stack depth {}", stack_depth) })?;
+        let (line, memory) = field("memory")?;
+        let strict_memory = match memory {
+            "wrap" => false,
+            "strict" => true,
+            _ => return Err(MovieError::Parse { line, message: format!("bad memory {}", memory) }),
+        };
+        let (line, machine_calls) = field("machine_calls")?;
+        let machine_call_errors = match machine_calls {
+            "ignore" => false,
+            "error" => true,
+            _ => return Err(MovieError::Parse { line, message: format!("bad machine calls {}", machine_calls) }),
+        };
+        let (line, rng) = field("rng")?;
+        let (rng, interpreter) = match rng.split_once(' ') {
+            Some((rng, interpreter)) => (rng, Some(interpreter)),
+            None => (rng, None),
+        };
+        let rng_mode = RngMode::from_name(rng).ok_or_else(|| MovieError::Parse { line, message: format!("unknown random number generator {}", rng) })?;
+        let vip_interpreter = match interpreter {
+            Some(hex) => match decode_hex(hex) {
+                Some(bytes) if bytes.len() == VIP_INTERPRETER_SIZE => Some(bytes),
+                _ => return Err(MovieError::Parse { line, message: "the COSMAC VIP interpreter should be 512 bytes in hex".into() }),
+            },
+            None => None,
+        };
+        let (line, seed) = field("seed")?;
+        let seed = parse_hex(seed, line)?;
+        field("frames")?;
+
+        let mut frames = vec![];
+        for (line, text) in lines.filter(|(_, text)| !text.is_empty()) {
+            let (keys, state_hash) = text.split_once(' ').ok_or(MovieError::Parse { line, message: "expected keys and a state hash".into() })?;
+            frames.push(MovieFrame {
+                keys: parse_hex(keys, line)? as u16,
+                state_hash: parse_hex(state_hash.trim(), line)?,
+            });
+        }
+
+        Ok(Self { rom_hash, platform, quirks, instructions_per_frame, timing, stack_depth, stack_in_memory, strict_memory, machine_call_errors, rng_mode, vip_interpreter, seed, frames })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "platform {}", self.platform.name())?;
+        let quirks: Vec<&str> = quirk_flags(&self.quirks).iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+        writeln!(f, "quirks {}", quirks.join(" "))?;
+        writeln!(f, "ipf {}", self.instructions_per_frame)?;
+        writeln!(f, "timing {}", self.timing.name())?;
+        let memory = if self.stack_in_memory { " memory" } else { "" };
+        writeln!(f, "stack {}{}", self.stack_depth, memory)?;
+        writeln!(f, "memory {}", if self.strict_memory { "strict" } else { "wrap" })?;
+        writeln!(f, "machine_calls {}", if self.machine_call_errors { "error" } else { "ignore" })?;
+        write!(f, "rng {}", self.rng_mode.name())?;
+        if let Some(interpreter) = &self.vip_interpreter {
+            let hex: String = interpreter.iter().map(|byte| format!("{:02x}", byte)).collect();
+            write!(f, " {}", hex)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "seed {:016x}", self.seed)?;
+        writeln!(f, "frames")?;
+        for frame in &self.frames {
+            writeln!(f, "{:04x} {:016x}", frame.keys, frame.state_hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// The keys of a recorded frame
+struct Keys(u16);
+
+impl Keypad for Keys {
+    fn is_key_down(&self, key: u8) -> bool {
+        key < 16 && self.0 & 1 << key != 0
+    }
+}
+
+/// FX55/FX65 moving I by X+1 keeps the name it had when it could only be on or off
+fn quirk_flags(quirks: &Quirks) -> [(&'static str, bool); 7] {
+    [
+        ("shift_uses_vy", quirks.shift_uses_vy),
+        ("load_store_increment_index", quirks.load_store_index_increment == IndexIncrement::XPlusOne),
+        ("load_store_increment_index_by_x", quirks.load_store_index_increment == IndexIncrement::X),
+        ("vf_reset", quirks.vf_reset),
+        ("jump_with_offset_uses_vx", quirks.jump_with_offset_uses_vx),
+        ("clip_sprites", quirks.clip_sprites),
+        ("display_wait", quirks.display_wait),
+    ]
+}
+
+/// The names of the quirks that are on, the rest are off
+fn parse_quirks(names: &str, line: usize) -> Result<Quirks, MovieError> {
+    let mut quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index_increment: IndexIncrement::None,
+        vf_reset: false,
+        jump_with_offset_uses_vx: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+    for name in names.split_whitespace() {
+        let flag = match name {
+            "shift_uses_vy" => &mut quirks.shift_uses_vy,
+            "load_store_increment_index" => {
+                quirks.load_store_index_increment = IndexIncrement::XPlusOne;
+                continue;
+            }
+            "load_store_increment_index_by_x" => {
+                quirks.load_store_index_increment = IndexIncrement::X;
+                continue;
+            }
+            "vf_reset" => &mut quirks.vf_reset,
+            "jump_with_offset_uses_vx" => &mut quirks.jump_with_offset_uses_vx,
+            "clip_sprites" => &mut quirks.clip_sprites,
+            "display_wait" => &mut quirks.display_wait,
+            _ => return Err(MovieError::Parse { line, message: format!("unknown quirk {}", name) }),
+        };
+        *flag = true;
+    }
+    Ok(quirks)
+}
+
+fn parse_hex(text: &str, line: usize) -> Result<u64, MovieError> {
+    u64::from_str_radix(text, 16).map_err(|_| MovieError::Parse { line, message: format!("bad hex number {}", text) })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// 64 bit FNV-1a, for the ROM and the state after each frame
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Headless;
+
+    // V0 = random, V1 counts frames with key 1 down: C0FF E1A1 7101 1200
+    const ROM: [u8; 8] = [0xC0, 0xFF, 0xE1, 0xA1, 0x71, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn plays_back_what_was_recorded() {
+        let mut movie = Movie::new(&ROM, Platform::Chip8, Quirks::cosmac_vip(), 11, Timing::Instructions, 0x1234);
+        let mut computer = movie.start(&ROM).unwrap();
+        let mut headless = Headless::new();
+        for frame in 0..30 {
+            headless.set_key(1, frame % 4 < 2);
+            movie.record_frame(&mut computer, &headless).unwrap();
+        }
+        let recorded = computer.save_state();
+
+        let movie = Movie::parse(&movie.to_string()).unwrap();
+        let mut computer = movie.start(&ROM).unwrap();
+        assert!((0..movie.frames().len()).all(|frame| movie.play_frame(frame, &mut computer).unwrap()));
+        assert_eq!(fnv1a(&computer.save_state()), fnv1a(&recorded));
+        assert_eq!(movie.frames().last().unwrap().state_hash, fnv1a(&recorded));
+    }
+
+    #[test]
+    fn notices_a_different_seed() {
+        let mut movie = Movie::new(&ROM, Platform::Chip8, Quirks::cosmac_vip(), 11, Timing::Instructions, 1);
+        let mut computer = movie.start(&ROM).unwrap();
+        movie.record_frame(&mut computer, &Headless::new()).unwrap();
+
+        movie.seed = 2;
+        let mut computer = movie.start(&ROM).unwrap();
+        assert!(!movie.play_frame(0, &mut computer).unwrap());
+    }
+
+    #[test]
+    fn formats_and_parses_to_the_same_movie() {
+        let mut movie = Movie::new(&ROM, Platform::Schip, Quirks::chip_48(), 30, Timing::CosmacVip, 0xfeed);
+        movie.stack_depth = 64;
+        movie.stack_in_memory = true;
+        movie.strict_memory = true;
+        movie.machine_call_errors = true;
+        movie.rng_mode = RngMode::Vip;
+        movie.vip_interpreter = Some((0..VIP_INTERPRETER_SIZE).map(|i| i as u8).collect());
+        movie.frames = vec![MovieFrame { keys: 0x8001, state_hash: 0x0123456789abcdef }, MovieFrame { keys: 0, state_hash: 1 }];
+
+        assert_eq!(Movie::parse(&movie.to_string()).unwrap(), movie);
+        assert_eq!(Movie::parse(&Movie::new(&ROM, Platform::Chip8, Quirks::cosmac_vip(), 11, Timing::Instructions, 0).to_string()).unwrap().frames(), []);
+    }
+
+    #[test]
+    fn rejects_other_roms_and_bad_lines() {
+        let movie = Movie::new(&ROM, Platform::Chip8, Quirks::cosmac_vip(), 11, Timing::Instructions, 0);
+        assert!(matches!(movie.start(&[0x12, 0x00]), Err(MovieError::RomMismatch { .. })));
+
+        let text = movie.to_string().replace("ipf 11", "ipf fast");
+        assert!(matches!(Movie::parse(&text), Err(MovieError::Parse { line: 5, .. })));
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn names_match_the_command_line() {
+        use clap::ValueEnum;
+
+        assert!(Platform::ALL.iter().all(|platform| platform.to_possible_value().unwrap().get_name() == platform.name()));
+        assert!(Timing::ALL.iter().all(|timing| timing.to_possible_value().unwrap().get_name() == timing.name()));
+        assert!(RngMode::ALL.iter().all(|mode| mode.to_possible_value().unwrap().get_name() == mode.name()));
+        assert_eq!(Platform::from_name("XO-CHIP"), Some(Platform::XoChip));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+/// A decoded instruction. Register operands are indices 0x0 to 0xF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00CN (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    /// 00DN (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    Lores,
+    /// 00FF (SUPER-CHIP)
+    Hires,
+    /// 0NNN, a call to COSMAC VIP machine code, any 0 instruction the platform has no other use for
+    MachineCall { nnn: u16 },
+    /// 1NNN
+    Jump { nnn: u16 },
+    /// 2NNN
+    Call { nnn: u16 },
+    /// 3XNN
+    SkipIfEqual { x: usize, nn: u8 },
+    /// 4XNN
+    SkipIfNotEqual { x: usize, nn: u8 },
+    /// 5XY0
+    SkipIfRegistersEqual { x: usize, y: usize },
+    /// 5XY2 (XO-CHIP)
+    StoreRegisterRange { x: usize, y: usize },
+    /// 5XY3 (XO-CHIP)
+    LoadRegisterRange { x: usize, y: usize },
+    /// 6XNN
+    SetImm { x: usize, nn: u8 },
+    /// 7XNN
+    AddImm { x: usize, nn: u8 },
+    /// 8XY0
+    Set { x: usize, y: usize },
+    /// 8XY1
+    Or { x: usize, y: usize },
+    /// 8XY2
+    And { x: usize, y: usize },
+    /// 8XY3
+    Xor { x: usize, y: usize },
+    /// 8XY4
+    Add { x: usize, y: usize },
+    /// 8XY5
+    Sub { x: usize, y: usize },
+    /// 8XY6
+    ShiftRight { x: usize, y: usize },
+    /// 8XY7
+    SubReverse { x: usize, y: usize },
+    /// 8XYE
+    ShiftLeft { x: usize, y: usize },
+    /// 9XY0
+    SkipIfRegistersNotEqual { x: usize, y: usize },
+    /// ANNN
+    SetIndex { nnn: u16 },
+    /// BNNN, or BXNN depending on the jump quirk
+    JumpWithOffset { nnn: u16 },
+    /// CXNN
+    Random { x: usize, nn: u8 },
+    /// DXYN
+    Draw { x: usize, y: usize, n: u8 },
+    /// EX9E
+    SkipIfKey { x: usize },
+    /// EXA1
+    SkipIfNotKey { x: usize },
+    /// F000 NNNN (XO-CHIP), the only 4 byte instruction
+    LongIndex { nnnn: u16 },
+    /// FN01 (XO-CHIP)
+    SelectPlanes { n: u8 },
+    /// F002 (XO-CHIP)
+    LoadAudioPattern,
+    /// FX07
+    GetDelay { x: usize },
+    /// FX0A
+    WaitKey { x: usize },
+    /// FX15
+    SetDelay { x: usize },
+    /// FX18
+    SetSound { x: usize },
+    /// FX1E
+    AddIndex { x: usize },
+    /// FX29
+    FontCharacter { x: usize },
+    /// FX30 (SUPER-CHIP)
+    BigFontCharacter { x: usize },
+    /// FX33
+    BinaryCodedDecimal { x: usize },
+    /// FX3A (XO-CHIP)
+    Pitch { x: usize },
+    /// FX55
+    Store { x: usize },
+    /// FX65
+    Load { x: usize },
+    /// FX75 (SUPER-CHIP)
+    StoreFlags { x: usize },
+    /// FX85 (SUPER-CHIP)
+    LoadFlags { x: usize },
+    /// Anything the platform does not implement
+    Unknown(u16),
+}
+
+impl Op {
+    /// Decode an instruction for the given platform. next_word is the word following the
+    /// instruction, only used by the 4 byte XO-CHIP F000 NNNN.
+    pub fn decode(instruction: Instruction, next_word: u16, platform: Platform) -> Op {
+        let schip = platform.has_schip_instructions();
+        let xo_chip = platform.has_xo_chip_instructions();
+
+        let x = instruction.x();
+        let y = instruction.y();
+        let n = instruction.n();
+        let nn = instruction.nn();
+        let nnn = instruction.nnn();
+
+        match instruction.op_code() {
+            0x0 => match nn {
+                0xE0 if x == 0 => Op::ClearScreen,
+                0xEE if x == 0 => Op::Return,
+                0xC0..=0xCF if schip && x == 0 => Op::ScrollDown { n },
+                0xD0..=0xDF if xo_chip && x == 0 => Op::ScrollUp { n },
+                0xFB if schip && x == 0 => Op::ScrollRight,
+                0xFC if schip && x == 0 => Op::ScrollLeft,
+                0xFD if schip && x == 0 => Op::Exit,
+                0xFE if schip && x == 0 => Op::Lores,
+                0xFF if schip && x == 0 => Op::Hires,
+                _ => Op::MachineCall { nnn },
+            },
+            0x1 => Op::Jump { nnn },
+            0x2 => Op::Call { nnn },
+            0x3 => Op::SkipIfEqual { x, nn },
+            0x4 => Op::SkipIfNotEqual { x, nn },
+            0x5 => match n {
+                0x0 => Op::SkipIfRegistersEqual { x, y },
+                0x2 if xo_chip => Op::StoreRegisterRange { x, y },
+                0x3 if xo_chip => Op::LoadRegisterRange { x, y },
+                _ => Op::Unknown(instruction.instruction),
+            },
+            0x6 => Op::SetImm { x, nn },
+            0x7 => Op::AddImm { x, nn },
+            0x8 => match n {
+                0x0 => Op::Set { x, y },
+                0x1 => Op::Or { x, y },
+                0x2 => Op::And { x, y },
+                0x3 => Op::Xor { x, y },
+                0x4 => Op::Add { x, y },
+                0x5 => Op::Sub { x, y },
+                0x6 => Op::ShiftRight { x, y },
+                0x7 => Op::SubReverse { x, y },
+                0xE => Op::ShiftLeft { x, y },
+                _ => Op::Unknown(instruction.instruction),
+            },
+            0x9 if n == 0 => Op::SkipIfRegistersNotEqual { x, y },
+            0xA => Op::SetIndex { nnn },
+            0xB => Op::JumpWithOffset { nnn },
+            0xC => Op::Random { x, nn },
+            0xD => Op::Draw { x, y, n },
+            0xE => match nn {
+                0x9E => Op::SkipIfKey { x },
+                0xA1 => Op::SkipIfNotKey { x },
+                _ => Op::Unknown(instruction.instruction),
+            },
+            0xF => match nn {
+                0x00 if xo_chip && x == 0 => Op::LongIndex { nnnn: next_word },
+                0x01 if xo_chip => Op::SelectPlanes { n: x as u8 },
+                0x02 if xo_chip && x == 0 => Op::LoadAudioPattern,
+                0x07 => Op::GetDelay { x },
+                0x0A => Op::WaitKey { x },
+                0x15 => Op::SetDelay { x },
+                0x18 => Op::SetSound { x },
+                0x1E => Op::AddIndex { x },
+                0x29 => Op::FontCharacter { x },
+                0x30 if schip => Op::BigFontCharacter { x },
+                0x33 => Op::BinaryCodedDecimal { x },
+                0x3A if xo_chip => Op::Pitch { x },
+                0x55 => Op::Store { x },
+                0x65 => Op::Load { x },
+                0x75 if schip => Op::StoreFlags { x },
+                0x85 if schip => Op::LoadFlags { x },
+                _ => Op::Unknown(instruction.instruction),
+            },
+            _ => Op::Unknown(instruction.instruction),
+        }
+    }
+
+    /// The size of the instruction in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Op::LongIndex { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encode back into the big endian bytes the instruction was decoded from
+    pub fn encode(&self) -> Vec<u8> {
+        let xy = |high: u16, x: usize, y: usize, low: u16| high << 12 | (x as u16) << 8 | (y as u16) << 4 | low;
+        let xnn = |high: u16, x: usize, nn: u8| high << 12 | (x as u16) << 8 | nn as u16;
+
+        let word = match *self {
+            Op::ClearScreen => 0x00E0,
+            Op::Return => 0x00EE,
+            Op::ScrollDown { n } => 0x00C0 | n as u16,
+            Op::ScrollUp { n } => 0x00D0 | n as u16,
+            Op::ScrollRight => 0x00FB,
+            Op::ScrollLeft => 0x00FC,
+            Op::Exit => 0x00FD,
+            Op::Lores => 0x00FE,
+            Op::Hires => 0x00FF,
+            Op::MachineCall { nnn } => nnn,
+            Op::Jump { nnn } => 0x1000 | nnn,
+            Op::Call { nnn } => 0x2000 | nnn,
+            Op::SkipIfEqual { x, nn } => xnn(0x3, x, nn),
+            Op::SkipIfNotEqual { x, nn } => xnn(0x4, x, nn),
+            Op::SkipIfRegistersEqual { x, y } => xy(0x5, x, y, 0x0),
+            Op::StoreRegisterRange { x, y } => xy(0x5, x, y, 0x2),
+            Op::LoadRegisterRange { x, y } => xy(0x5, x, y, 0x3),
+            Op::SetImm { x, nn } => xnn(0x6, x, nn),
+            Op::AddImm { x, nn } => xnn(0x7, x, nn),
+            Op::Set { x, y } => xy(0x8, x, y, 0x0),
+            Op::Or { x, y } => xy(0x8, x, y, 0x1),
+            Op::And { x, y } => xy(0x8, x, y, 0x2),
+            Op::Xor { x, y } => xy(0x8, x, y, 0x3),
+            Op::Add { x, y } => xy(0x8, x, y, 0x4),
+            Op::Sub { x, y } => xy(0x8, x, y, 0x5),
+            Op::ShiftRight { x, y } => xy(0x8, x, y, 0x6),
+            Op::SubReverse { x, y } => xy(0x8, x, y, 0x7),
+            Op::ShiftLeft { x, y } => xy(0x8, x, y, 0xE),
+            Op::SkipIfRegistersNotEqual { x, y } => xy(0x9, x, y, 0x0),
+            Op::SetIndex { nnn } => 0xA000 | nnn,
+            Op::JumpWithOffset { nnn } => 0xB000 | nnn,
+            Op::Random { x, nn } => xnn(0xC, x, nn),
+            Op::Draw { x, y, n } => xy(0xD, x, y, n as u16),
+            Op::SkipIfKey { x } => xnn(0xE, x, 0x9E),
+            Op::SkipIfNotKey { x } => xnn(0xE, x, 0xA1),
+            Op::LongIndex { .. } => 0xF000,
+            Op::SelectPlanes { n } => xnn(0xF, n as usize, 0x01),
+            Op::LoadAudioPattern => 0xF002,
+            Op::GetDelay { x } => xnn(0xF, x, 0x07),
+            Op::WaitKey { x } => xnn(0xF, x, 0x0A),
+            Op::SetDelay { x } => xnn(0xF, x, 0x15),
+            Op::SetSound { x } => xnn(0xF, x, 0x18),
+            Op::AddIndex { x } => xnn(0xF, x, 0x1E),
+            Op::FontCharacter { x } => xnn(0xF, x, 0x29),
+            Op::BigFontCharacter { x } => xnn(0xF, x, 0x30),
+            Op::BinaryCodedDecimal { x } => xnn(0xF, x, 0x33),
+            Op::Pitch { x } => xnn(0xF, x, 0x3A),
+            Op::Store { x } => xnn(0xF, x, 0x55),
+            Op::Load { x } => xnn(0xF, x, 0x65),
+            Op::StoreFlags { x } => xnn(0xF, x, 0x75),
+            Op::LoadFlags { x } => xnn(0xF, x, 0x85),
+            Op::Unknown(instruction) => instruction,
+        };
+
+        let mut bytes = word.to_be_bytes().to_vec();
+        if let Op::LongIndex { nnnn } = *self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_what_it_decoded() {
+        for platform in [Platform::Chip8, Platform::Schip, Platform::XoChip] {
+            for word in 0..=0xFFFF {
+                let op = Op::decode(Instruction::new(word), 0xBEEF, platform);
+                let mut bytes = word.to_be_bytes().to_vec();
+                if op.size() == 4 {
+                    bytes.extend_from_slice(&0xBEEFu16.to_be_bytes());
+                }
+                assert_eq!(op.encode(), bytes, "{:04X} on {:?} decoded as {:?}", word, platform, op);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_by_platform() {
+        let decode = |word, platform| Op::decode(Instruction::new(word), 0x1234, platform);
+        assert_eq!(decode(0x00FF, Platform::Chip8), Op::MachineCall { nnn: 0x0FF });
+        assert_eq!(decode(0x00FF, Platform::Schip), Op::Hires);
+        assert_eq!(decode(0xF000, Platform::Schip), Op::Unknown(0xF000));
+        assert_eq!(decode(0xF000, Platform::XoChip), Op::LongIndex { nnnn: 0x1234 });
+        assert_eq!(decode(0x5123, Platform::XoChip), Op::LoadRegisterRange { x: 1, y: 2 });
+    }
+}
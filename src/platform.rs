@@ -0,0 +1,82 @@
+use crate::error::EmulatorError;
+use crate::quirks::Quirks;
+use crate::save_state::{invalid, StateReader, StateWriter};
+use crate::stack::{SCHIP_STACK_DEPTH, VIP_STACK_DEPTH};
+
+/// The CHIP-8 variant being emulated, which decides which opcodes are available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Platform {
+    /// The original CHIP-8 instruction set
+    Chip8,
+    /// SUPER-CHIP 1.1: 128x64 high resolution mode, scrolling, big font and RPL flags
+    Schip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bit planes and an audio pattern buffer
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Self; 3] = [Platform::Chip8, Platform::Schip, Platform::XoChip];
+
+    /// The name used on the command line, in movies and in DAP launch arguments
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Schip => "schip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+
+    /// Self::name's inverse, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn has_schip_instructions(&self) -> bool {
+        match self {
+            Platform::Chip8 => false,
+            Platform::Schip | Platform::XoChip => true,
+        }
+    }
+
+    pub fn has_xo_chip_instructions(&self) -> bool {
+        *self == Platform::XoChip
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::Schip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// How deep subroutine calls can nest
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Platform::Chip8 => VIP_STACK_DEPTH,
+            Platform::Schip | Platform::XoChip => SCHIP_STACK_DEPTH,
+        }
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        match state.u8()? {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::Schip),
+            2 => Ok(Platform::XoChip),
+            platform => Err(invalid(format!("unknown platform {}", platform))),
+        }
+    }
+
+    /// The quirks used when none are explicitly selected
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::cosmac_vip(),
+            Platform::Schip => Quirks::super_chip(),
+            Platform::XoChip => Quirks::xo_chip(),
+        }
+    }
+}
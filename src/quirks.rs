@@ -0,0 +1,161 @@
+use crate::error::EmulatorError;
+use crate::save_state::{invalid, StateReader, StateWriter};
+
+/// Behaviour of the CHIP-8 instructions that differ between interpreters.
+/// https://github.com/Timendus/chip8-test-suite#quirks-test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting (COSMAC VIP) instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// How far FX55/FX65 move I after storing/loading V0 to VX
+    pub load_store_index_increment: IndexIncrement,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// BNNN jumps to XNN + VX (BXNN) instead of NNN + V0
+    pub jump_with_offset_uses_vx: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60Hz vertical blank before execution continues
+    pub display_wait: bool,
+}
+
+/// How far FX55/FX65 move I.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is left where it was (SUPER-CHIP)
+    None,
+    /// I is left pointing at the last register stored/loaded (CHIP-48)
+    X,
+    /// I is left pointing past the last register stored/loaded (COSMAC VIP)
+    XPlusOne,
+}
+
+impl IndexIncrement {
+    /// How much I moves by after storing/loading V0 to VX
+    pub fn amount(&self, x: usize) -> usize {
+        match self {
+            IndexIncrement::None => 0,
+            IndexIncrement::X => x,
+            IndexIncrement::XPlusOne => x + 1,
+        }
+    }
+}
+
+/// Named quirk profiles matching well known interpreters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum QuirksPreset {
+    /// The original COSMAC VIP interpreter
+    Vip,
+    /// CHIP-48 on the HP-48 calculators
+    Chip48,
+    /// SUPER-CHIP 1.1
+    Schip,
+    /// XO-CHIP as implemented by Octo
+    XoChip,
+}
+
+impl QuirksPreset {
+    pub const ALL: [Self; 4] = [QuirksPreset::Vip, QuirksPreset::Chip48, QuirksPreset::Schip, QuirksPreset::XoChip];
+
+    /// The name `--quirks` and the DAP launch argument take
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuirksPreset::Vip => "vip",
+            QuirksPreset::Chip48 => "chip48",
+            QuirksPreset::Schip => "schip",
+            QuirksPreset::XoChip => "xo-chip",
+        }
+    }
+
+    /// The preset with that name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_index_increment: IndexIncrement::XPlusOne,
+            vf_reset: true,
+            jump_with_offset_uses_vx: false,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip_48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_index_increment: IndexIncrement::X,
+            vf_reset: false,
+            jump_with_offset_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn super_chip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_index_increment: IndexIncrement::None,
+            vf_reset: false,
+            jump_with_offset_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_index_increment: IndexIncrement::XPlusOne,
+            vf_reset: false,
+            jump_with_offset_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.shift_uses_vy);
+        state.u8(self.load_store_index_increment as u8);
+        state.bool(self.vf_reset);
+        state.bool(self.jump_with_offset_uses_vx);
+        state.bool(self.clip_sprites);
+        state.bool(self.display_wait);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        Ok(Self {
+            shift_uses_vy: state.bool()?,
+            load_store_index_increment: match state.u8()? {
+                0 => IndexIncrement::None,
+                1 => IndexIncrement::X,
+                2 => IndexIncrement::XPlusOne,
+                increment => return Err(invalid(format!("unknown index increment {}", increment))),
+            },
+            vf_reset: state.bool()?,
+            jump_with_offset_uses_vx: state.bool()?,
+            clip_sprites: state.bool()?,
+            display_wait: state.bool()?,
+        })
+    }
+
+    pub fn from_preset(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::Vip => Self::cosmac_vip(),
+            QuirksPreset::Chip48 => Self::chip_48(),
+            QuirksPreset::Schip => Self::super_chip(),
+            QuirksPreset::XoChip => Self::xo_chip(),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::cosmac_vip()
+    }
+}
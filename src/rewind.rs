@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+/// Snapshots of the last few seconds of play, newest last, for stepping backwards a frame at a time.
+///
+/// Only the newest save state is kept whole. Each older one is stored as the difference from
+/// the state after it: the two XORed together, which is zero wherever memory and the screen didn't
+/// change, with the runs of zeros left out. Once the snapshots outgrow the memory budget the
+/// oldest are dropped.
+pub struct Rewind {
+    /// The newest state, the one the program is in now
+    latest: Vec<u8>,
+    /// Each one turns the state after it into the state before, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    budget: usize,
+    used: usize,
+}
+
+// the first byte of a delta
+const DELTA: u8 = 0;
+/// The states were different sizes, e.g. a state for another platform was loaded, so the delta
+/// is the older state itself
+const WHOLE: u8 = 1;
+
+impl Rewind {
+    /// Keep as many snapshots as fit in budget bytes
+    pub fn new(budget: usize) -> Self {
+        Self {
+            latest: vec![],
+            deltas: VecDeque::new(),
+            budget,
+            used: 0,
+        }
+    }
+
+    /// How many frames back the program can go
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Bytes used by the snapshots
+    pub fn memory_used(&self) -> usize {
+        self.used + self.latest.len()
+    }
+
+    /// Remember the state of the frame just run, from `Computer::save_state`. A state the same as
+    /// the last one, e.g. while paused, is not stored twice
+    pub fn push(&mut self, state: Vec<u8>) {
+        if state == self.latest {
+            return;
+        }
+
+        if !self.latest.is_empty() {
+            let delta = encode(&state, &self.latest);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = state;
+
+        while self.memory_used() > self.budget {
+            let Some(oldest) = self.deltas.pop_front() else {
+                break;
+            };
+            self.used -= oldest.len();
+        }
+    }
+
+    /// Go back one frame, returning the state to give to `Computer::load_state`.
+    /// None once there is nothing older
+    pub fn step_back(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        self.used -= delta.len();
+        self.latest = decode(&self.latest, &delta);
+        Some(&self.latest)
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+        self.used = 0;
+    }
+}
+
+/// What turns newer back into older: runs of (zeros to skip, literal length, literal bytes),
+/// the lengths as LEB128
+fn encode(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    if newer.len() != older.len() {
+        let mut delta = vec![WHOLE];
+        delta.extend_from_slice(older);
+        return delta;
+    }
+
+    let mut delta = vec![DELTA];
+    let difference: Vec<u8> = newer.iter().zip(older).map(|(a, b)| a ^ b).collect();
+    let mut position = 0;
+    while position < difference.len() {
+        let zeros = difference[position..].iter().take_while(|&&byte| byte == 0).count();
+        position += zeros;
+        if position == difference.len() {
+            break;
+        }
+        let literal = difference[position..].iter().take_while(|&&byte| byte != 0).count();
+        write_length(&mut delta, zeros);
+        write_length(&mut delta, literal);
+        delta.extend_from_slice(&difference[position..position + literal]);
+        position += literal;
+    }
+    delta
+}
+
+fn decode(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == WHOLE {
+        return delta[1..].to_vec();
+    }
+
+    let mut older = newer.to_vec();
+    let mut position = 0;
+    let mut input = &delta[1..];
+    while !input.is_empty() {
+        position += read_length(&mut input);
+        let literal = read_length(&mut input);
+        for (byte, difference) in older[position..position + literal].iter_mut().zip(&input[..literal]) {
+            *byte ^= difference;
+        }
+        input = &input[literal..];
+        position += literal;
+    }
+    older
+}
+
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        output.push(length as u8 | 0x80);
+        length >>= 7;
+    }
+    output.push(length as u8);
+}
+
+fn read_length(input: &mut &[u8]) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        length |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, Headless, Platform};
+
+    #[test]
+    fn steps_back_through_the_frames() {
+        // count in v0 and store it in memory, round and round
+        let rom = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+        let mut computer = Computer::new(Platform::Chip8, Platform::Chip8.default_quirks());
+        computer.load_program(&rom).unwrap();
+        let mut rewind = Rewind::new(1 << 20);
+
+        let mut states = vec![];
+        for _ in 0..20 {
+            computer.run_frame(&Headless::new()).unwrap();
+            states.push(computer.save_state());
+            rewind.push(computer.save_state());
+        }
+        assert_eq!(rewind.len(), 19);
+        // the deltas are much smaller than whole states
+        assert!(rewind.memory_used() < 2 * states[0].len());
+
+        for state in states.iter().rev().skip(1) {
+            let older = rewind.step_back().unwrap().to_vec();
+            assert_eq!(&older, state);
+            computer.load_state(&older).unwrap();
+        }
+        assert!(rewind.step_back().is_none());
+        // back to the first frame, 11 instructions, three times round the loop
+        assert_eq!(computer.registers()[0], 3);
+    }
+
+    #[test]
+    fn keeps_states_of_different_sizes() {
+        let mut rewind = Rewind::new(1 << 20);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 3, 4, 5]);
+        rewind.push(vec![1, 0, 3, 4, 5]);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.step_back(), Some(&[1, 2, 3, 4, 5][..]));
+        assert_eq!(rewind.step_back(), Some(&[1, 2, 3][..]));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_states_past_the_budget() {
+        let mut rewind = Rewind::new(64);
+        for i in 0..100 {
+            rewind.push(vec![i; 16]);
+        }
+        assert!(rewind.memory_used() <= 64);
+        assert!(rewind.len() < 99);
+        let mut oldest = 99;
+        while let Some(state) = rewind.step_back() {
+            oldest = state[0];
+        }
+        assert!(oldest > 0);
+    }
+}
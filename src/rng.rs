@@ -0,0 +1,174 @@
+use crate::error::EmulatorError;
+use crate::save_state::{invalid, StateReader, StateWriter};
+
+/// Size of the COSMAC VIP's CHIP-8 interpreter, which it ran from 0x000 to 0x1FF
+pub const VIP_INTERPRETER_SIZE: usize = 0x200;
+
+/// Which algorithm makes the CXNN random numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RngMode {
+    /// SplitMix64, good quality numbers
+    #[default]
+    #[cfg_attr(feature = "cli", value(name = "splitmix64"))]
+    SplitMix64,
+    /// The COSMAC VIP interpreter's own routine, which mixes a counter with the bytes of the
+    /// interpreter's second page. It needs that page, from `Computer::set_vip_interpreter`
+    Vip,
+}
+
+impl RngMode {
+    pub const ALL: [Self; 2] = [RngMode::SplitMix64, RngMode::Vip];
+
+    /// The name for `--rng`, also written in movies
+    pub fn name(&self) -> &'static str {
+        match self {
+            RngMode::SplitMix64 => "splitmix64",
+            RngMode::Vip => "vip",
+        }
+    }
+
+    /// The mode with that name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name().eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        match state.u8()? {
+            0 => Ok(RngMode::SplitMix64),
+            1 => Ok(RngMode::Vip),
+            mode => Err(invalid(format!("unknown random number generator {}", mode))),
+        }
+    }
+}
+
+/// The random number generator behind CXNN. It is part of the machine state, so it goes in
+/// save states and the same seed always gives the same program run.
+///
+/// In VIP mode only the low 16 bits of the state are used, as the interpreter's R9 register,
+/// and the numbers come from the interpreter's code page 0x100-0x1FF. That page isn't in
+/// emulated memory, so it is kept here and reads as zeros until it is given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rng {
+    mode: RngMode,
+    state: u64,
+    vip_page: [u8; 0x100],
+}
+
+impl Rng {
+    pub fn new(mode: RngMode, seed: u64) -> Self {
+        Self {
+            mode,
+            state: seed,
+            vip_page: [0; 0x100],
+        }
+    }
+
+    /// Seeded from the operating system, a different run every time
+    pub fn from_entropy(mode: RngMode) -> Self {
+        Self::new(mode, rand::random())
+    }
+
+    pub fn mode(&self) -> RngMode {
+        self.mode
+    }
+
+    /// Carries on from the current state with mode's algorithm
+    pub fn set_mode(&mut self, mode: RngMode) {
+        self.mode = mode;
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        self.state = seed;
+    }
+
+    /// Take the page VIP mode reads from a dump of the whole interpreter
+    pub fn set_vip_interpreter(&mut self, interpreter: &[u8]) -> Result<(), EmulatorError> {
+        if interpreter.len() != VIP_INTERPRETER_SIZE {
+            return Err(EmulatorError::BadInterpreter { size: interpreter.len() });
+        }
+        self.vip_page.copy_from_slice(&interpreter[0x100..]);
+        Ok(())
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        self.mode.save_state(state);
+        state.u64(self.state);
+        state.array(&self.vip_page);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        let mut rng = Self::new(RngMode::load_state(state)?, state.u64()?);
+        rng.vip_page.copy_from_slice(state.array(0x100)?);
+        Ok(rng)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// The next number, before CXNN masks it with NN
+    pub fn next_u8(&mut self) -> u8 {
+        match self.mode {
+            RngMode::SplitMix64 => (self.next_u64() >> 56) as u8,
+            RngMode::Vip => {
+                // the interpreter's CXNN routine, 1802 code:
+                // INC R9; GLO R9; PLO RE; GHI R3; PHI RE    RE points at byte R9.0 of its own page
+                // GHI R9; SEX RE; ADD; STR R6               VX = that byte + R9.1, DF the carry
+                // SHRC; SEX R6; ADD; PHI R9                 R9.1 = VX + (VX >> 1 with DF on top)
+                let r9 = (self.state as u16).wrapping_add(1);
+                let [high, low] = r9.to_be_bytes();
+                let (sum, carry) = self.vip_page[low as usize].overflowing_add(high);
+                let shifted = sum >> 1 | (carry as u8) << 7;
+                let value = sum.wrapping_add(shifted);
+                self.state = u16::from_be_bytes([value, low]) as u64;
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vip_mode_runs_the_interpreter_routine() {
+        let mut interpreter = [0; VIP_INTERPRETER_SIZE];
+        for (i, byte) in interpreter[0x100..].iter_mut().enumerate() {
+            *byte = (i * 0x9D + 0x3B) as u8;
+        }
+        let mut rng = Rng::new(RngMode::Vip, 0xBEEF);
+        rng.set_vip_interpreter(&interpreter).unwrap();
+
+        let numbers: Vec<u8> = (0..8).map(|_| rng.next_u8()).collect();
+        assert_eq!(numbers, [0xBD, 0x27, 0x32, 0xAE, 0x53, 0x36, 0x76, 0xC2]);
+    }
+
+    #[test]
+    fn vip_mode_only_reads_the_second_page() {
+        let mut interpreter = [0xFF; VIP_INTERPRETER_SIZE];
+        interpreter[0x100..].fill(0);
+        let mut rng = Rng::new(RngMode::Vip, 0);
+        rng.set_vip_interpreter(&interpreter).unwrap();
+
+        // R9 counts up from 1 in the low byte and the high byte stays 0
+        assert!((0..255).all(|_| rng.next_u8() == 0));
+        assert!(rng.set_vip_interpreter(&interpreter[..0x100]).is_err());
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_numbers() {
+        let mut a = Rng::new(RngMode::SplitMix64, 1234);
+        let mut b = Rng::new(RngMode::SplitMix64, 1234);
+        assert!((0..100).all(|_| a.next_u8() == b.next_u8()));
+    }
+}
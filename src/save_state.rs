@@ -0,0 +1,219 @@
+//! The binary save state format. A state starts with the magic bytes `C8ST` and a little endian
+//! u16 version, followed by the machine's fields in a fixed order. Numbers are little endian,
+//! bools are one byte, and variable length data is prefixed with its u32 length.
+//! Bump `VERSION` whenever the layout changes, old states are then refused rather than misread.
+
+use crate::error::EmulatorError;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+pub(crate) const VERSION: u16 = 6;
+
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        Self {
+            bytes,
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// A fixed number of bytes, the reader must know how many
+    pub fn array(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Bytes prefixed with their length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.array(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the magic bytes and version
+    pub fn new(bytes: &'a [u8]) -> Result<Self, EmulatorError> {
+        let mut reader = Self { bytes, position: 0 };
+        if reader.array(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a save state".into()));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(invalid(format!("version {} states can't be loaded, only version {}", version, VERSION)));
+        }
+        Ok(reader)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.array(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, EmulatorError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, EmulatorError> {
+        Ok(u16::from_le_bytes(self.array(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, EmulatorError> {
+        Ok(u32::from_le_bytes(self.array(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, EmulatorError> {
+        Ok(u64::from_le_bytes(self.array(8)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, EmulatorError> {
+        Ok(f32::from_le_bytes(self.array(4)?.try_into().unwrap()))
+    }
+
+    pub fn array(&mut self, length: usize) -> Result<&'a [u8], EmulatorError> {
+        let end = self.position + length;
+        let bytes = self.bytes.get(self.position..end).ok_or_else(|| invalid("state is cut short".into()))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], EmulatorError> {
+        let length = self.u32()? as usize;
+        self.array(length)
+    }
+
+    /// Error if anything is left over, which means the layout doesn't match
+    pub fn finish(self) -> Result<(), EmulatorError> {
+        if self.position != self.bytes.len() {
+            return Err(invalid("unexpected data after the end of the state".into()));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn invalid(reason: String) -> EmulatorError {
+    EmulatorError::InvalidSaveState { reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, Headless, Platform, Quirks};
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut writer = StateWriter::new();
+        writer.u8(0xAB);
+        writer.bool(true);
+        writer.u16(0x1234);
+        writer.u32(0xDEADBEEF);
+        writer.u64(u64::MAX - 1);
+        writer.f32(0.25);
+        writer.array(&[1, 2, 3]);
+        writer.bytes(&[4, 5]);
+        let bytes = writer.finish();
+
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(reader.u8().unwrap(), 0xAB);
+        assert!(reader.bool().unwrap());
+        assert_eq!(reader.u16().unwrap(), 0x1234);
+        assert_eq!(reader.u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(reader.u64().unwrap(), u64::MAX - 1);
+        assert_eq!(reader.f32().unwrap(), 0.25);
+        assert_eq!(reader.array(3).unwrap(), [1, 2, 3]);
+        assert_eq!(reader.bytes().unwrap(), [4, 5]);
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        let mut state = StateWriter::new().finish();
+        assert!(StateReader::new(&state).is_ok());
+
+        state[0] = b'X';
+        assert!(matches!(StateReader::new(&state), Err(EmulatorError::InvalidSaveState { .. })));
+
+        let mut state = StateWriter::new().finish();
+        state[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(StateReader::new(&state), Err(EmulatorError::InvalidSaveState { .. })));
+        assert!(StateReader::new(b"C8").is_err());
+    }
+
+    #[test]
+    fn rejects_short_and_long_states() {
+        let mut writer = StateWriter::new();
+        writer.u16(7);
+        let mut bytes = writer.finish();
+
+        let mut reader = StateReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(reader.u16().is_err());
+
+        bytes.push(0);
+        let mut reader = StateReader::new(&bytes).unwrap();
+        reader.u16().unwrap();
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn a_loaded_computer_carries_on_the_same() {
+        // count in v0, draw it, wait a frame with the delay timer, loop
+        let rom = [0x70, 0x01, 0xF0, 0x29, 0xD1, 0x15, 0xF0, 0x15, 0x12, 0x00];
+        let mut computer = Computer::new(Platform::Schip, Quirks::super_chip());
+        computer.load_program(&rom).unwrap();
+        let keypad = Headless::new();
+        for _ in 0..10 {
+            computer.run_frame(&keypad).unwrap();
+        }
+        let state = computer.save_state();
+
+        let mut loaded = Computer::from_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        for _ in 0..10 {
+            computer.run_frame(&keypad).unwrap();
+            loaded.run_frame(&keypad).unwrap();
+        }
+        assert_eq!(loaded.save_state(), computer.save_state());
+        assert_eq!(loaded.platform(), Platform::Schip);
+
+        // a bad state leaves the computer as it was
+        let before = computer.save_state();
+        assert!(computer.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(computer.save_state(), before);
+        computer.load_state(&state).unwrap();
+        assert_eq!(computer.save_state(), state);
+    }
+}
@@ -1,32 +1,72 @@
+use sdl2::audio::{AudioDevice, AudioSpecDesired};
 use sdl2::gfx::primitives::DrawRenderer;
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
 use sdl2::rect::Rect;
 use sdl2::Sdl;
-use sdl2::video::WindowPos;
+use sdl2::event::WindowEvent;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::WindowCanvas;
-//use sdl2::render::Texture;
+use sdl2::render::Texture;
 use sdl2::render::TextureCreator;
 //use sdl2::image::LoadTexture;
-use sdl2::video::WindowContext;
+use sdl2::video::{FullscreenType, WindowContext};
+
+use rust_chip_8::{AudioOutput, FrameSink, Tone};
+
+use crate::audio::{AudioSettings, Voice};
+use crate::window_geometry::WindowGeometry;
+
+// Colours for each combination of the two XO-CHIP bit planes
+const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(255, 102, 0),
+    Color::RGB(102, 34, 0),
+];
+// Fills the window around the screen when its shape doesn't match
+const LETTERBOX: Color = Color::RGB(0, 0, 0);
 
 pub struct SdlSystem {
     pub sdl_context: Sdl,
     //window: Window,
     pub canvas: WindowCanvas,
-    pub texture_creator: TextureCreator<WindowContext>
+    pub texture_creator: TextureCreator<WindowContext>,
+    audio: Option<AudioDevice<Voice>>,
+    muted: bool,
+    /// The CHIP-8 screen, redrawn whole from pixels every frame it changes
+    screen: Option<Texture>,
+    screen_size: (usize, usize),
+    /// RGB bytes for the screen texture
+    rgb: Vec<u8>,
+    /// Scale the screen by whole numbers only, so every pixel is the same size
+    integer_scaling: bool,
+    /// Where the window is when it isn't fullscreen
+    windowed: WindowGeometry,
 }
 
 impl SdlSystem {
-    pub fn new(title: &str, width: u32, height: u32) -> SdlSystem {
+    pub fn new(title: &str, geometry: WindowGeometry, integer_scaling: bool) -> SdlSystem {
         let sdl_context = sdl2::init().unwrap();
         let video_subsystem = sdl_context.video().unwrap();
+        // scaled up pixels stay sharp
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
 
-        let mut window = video_subsystem.window(title, width, height)
-            //.position_centered()
-            .build()
+        let mut builder = video_subsystem.window(title, geometry.width, geometry.height);
+        builder.resizable();
+        match geometry.position {
+            Some((x, y)) => builder.position(x, y),
+            None => builder.position_centered(),
+        };
+        let mut window = builder.build()
             .expect("could not initialize video subsystem");
-        window.set_position(WindowPos::Positioned(0), WindowPos::Positioned(0)); // easy to debug
+        window.set_minimum_size(64, 32).ok();
+
+        let (x, y) = window.position();
+        let windowed = WindowGeometry { position: Some((x, y)), fullscreen: false, ..geometry };
+        if geometry.fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop).ok();
+        }
 
         let canvas = window.into_canvas().build()
             .expect("could not make a canvas");
@@ -38,10 +78,88 @@ impl SdlSystem {
             sdl_context,
             //window,
             canvas,
-            texture_creator
+            texture_creator,
+            audio: None,
+            muted: false,
+            screen: None,
+            screen_size: (0, 0),
+            rgb: vec![],
+            integer_scaling,
+            windowed,
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    /// Switch between a window and the whole desktop, returning true if now fullscreen
+    pub fn toggle_fullscreen(&mut self) -> Result<bool, String> {
+        let fullscreen = !self.is_fullscreen();
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(mode)?;
+        self.redraw();
+        Ok(fullscreen)
+    }
+
+    /// The geometry to remember for next time, the windowed size and position even when fullscreen
+    pub fn geometry(&self) -> WindowGeometry {
+        WindowGeometry { fullscreen: self.is_fullscreen(), ..self.windowed }
+    }
+
+    /// Keep track of the window being moved or resized, and draw it again at its new size
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        let windowed = !self.is_fullscreen();
+        match *event {
+            WindowEvent::Moved(x, y) if windowed => self.windowed.position = Some((x, y)),
+            WindowEvent::Resized(width, height) if windowed => {
+                self.windowed.width = width as u32;
+                self.windowed.height = height as u32;
+            }
+            _ => {}
+        }
+
+        if let WindowEvent::SizeChanged(..) | WindowEvent::Exposed = event {
+            self.redraw();
         }
     }
 
+    /// Draw the last screen presented again, scaled to fit the window
+    pub fn redraw(&mut self) {
+        self.canvas.set_draw_color(LETTERBOX);
+        self.canvas.clear();
+
+        if let Some(screen) = &self.screen {
+            let output = self.canvas.output_size().unwrap();
+            let destination = fit(self.screen_size, output, self.integer_scaling);
+            self.canvas.copy(screen, None, destination).unwrap();
+        }
+
+        self.canvas.present();
+    }
+
+    pub fn open_audio(&mut self, settings: AudioSettings) -> Result<(), String> {
+        let audio_subsystem = self.sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44_100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            Voice::new(settings, spec.freq)
+        })?;
+        device.resume();
+
+        self.audio = Some(device);
+        Ok(())
+    }
+
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.muted
+    }
+
     /* 
     pub fn load_texture(&self, path: &str) -> Texture {
         //let texture_creator = self.canvas.texture_creator();
@@ -57,8 +175,8 @@ impl SdlSystem {
     pub fn draw_filled_rect(&mut self, pos: Point, size: Point, color: Color) {
         self.canvas.set_draw_color(color);
 
-        let pos_x = i32::try_from(pos.x).unwrap();
-        let pos_y = i32::try_from(pos.y).unwrap();
+        let pos_x = pos.x;
+        let pos_y = pos.y;
 
         let width = u32::try_from(size.x).unwrap();
         let height = u32::try_from(size.y).unwrap();
@@ -94,7 +212,7 @@ impl SdlSystem {
 
         let pos_x = i16::try_from(point.x).unwrap();
         let pos_y = i16::try_from(point.y).unwrap();
-        let r = i16::try_from(radius as i32).unwrap();
+        let r = i16::try_from(radius).unwrap();
         self.canvas.filled_circle(pos_x, pos_y, r, color).unwrap();
     }
 
@@ -128,4 +246,50 @@ impl SdlSystem {
             //::std::thread::sleep(Duration::from::new(0, 1_000_000_000u32 / 60));
         }*/
     } */
-}
\ No newline at end of file
+}
+
+impl AudioOutput for SdlSystem {
+    fn play(&mut self, tone: Option<Tone>) {
+        let tone = if self.muted { None } else { tone };
+        if let Some(device) = self.audio.as_mut() {
+            device.lock().tone = tone;
+        }
+    }
+}
+
+impl FrameSink for SdlSystem {
+    fn present(&mut self, width: usize, height: usize, pixels: &[u8]) {
+        // the screen changes size when switching between lores and hires
+        if self.screen.is_none() || self.screen_size != (width, height) {
+            let texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .expect("could not make the screen texture");
+            if let Some(old) = self.screen.replace(texture) {
+                // Safety: the renderer that made it is still alive, and it isn't used again
+                unsafe { old.destroy() };
+            }
+            self.screen_size = (width, height);
+        }
+
+        self.rgb.clear();
+        for &pixel in pixels {
+            let color = PALETTE[pixel as usize];
+            self.rgb.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+        self.screen.as_mut().unwrap().update(None, &self.rgb, width * 3).unwrap();
+
+        self.redraw();
+    }
+}
+
+/// Where the screen goes in the window: as large as fits with its aspect ratio kept, centred
+/// with bars either side. With integer scaling only whole multiples of the screen size are used
+fn fit((width, height): (usize, usize), (output_width, output_height): (u32, u32), integer_scaling: bool) -> Rect {
+    let scale = (output_width as f32 / width as f32).min(output_height as f32 / height as f32);
+    let scale = if integer_scaling { scale.floor().max(1.0) } else { scale };
+    let scaled_width = (width as f32 * scale) as u32;
+    let scaled_height = (height as f32 * scale) as u32;
+
+    let x = (output_width as i32 - scaled_width as i32) / 2;
+    let y = (output_height as i32 - scaled_height as i32) / 2;
+    Rect::new(x, y, scaled_width, scaled_height)
+}
@@ -1,20 +1,114 @@
+use crate::error::EmulatorError;
+use crate::save_state::{invalid, StateReader, StateWriter};
+
+/// The deepest nesting of subroutine calls the COSMAC VIP interpreter allows
+pub const VIP_STACK_DEPTH: usize = 12;
+/// The deepest nesting of subroutine calls on SUPER-CHIP
+pub const SCHIP_STACK_DEPTH: usize = 16;
+/// The VIP keeps its stack in RAM from 0xECF down to 0xEA0, above the interpreter's variables
+pub const VIP_STACK_ADDRESS: usize = 0xEA0;
+const VIP_STACK_END: usize = 0xED0;
+// save states with a deeper stack are corrupt
+const MAX_DEPTH: usize = 255;
+
+/// The return addresses of the subroutine calls in progress.
+///
+/// The stack can also be kept in emulated RAM where the VIP interpreter keeps it, for ROMs that
+/// peek at it or change it. Each return address then takes two bytes, high byte first, with the
+/// first call at 0xECE and each deeper one two bytes below. The addresses stay here too, but a
+/// return takes its address from RAM.
 pub struct Stack {
-    stack: Vec<usize>
+    stack: Vec<usize>,
+    max_depth: usize,
+    in_memory: bool,
 }
 
 impl Stack {
-    pub fn new() -> Self {
+    pub fn new(max_depth: usize) -> Self {
         Self {
             stack: vec![],
+            max_depth,
+            in_memory: false,
         }
     }
 
-    pub fn push(&mut self, value: usize) {
+    pub fn push(&mut self, value: usize) -> Result<(), EmulatorError> {
+        if self.stack.len() >= self.max_depth() {
+            return Err(EmulatorError::StackOverflow);
+        }
         self.stack.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<usize, EmulatorError> {
+        self.stack.pop().ok_or(EmulatorError::StackUnderflow)
+    }
+
+    /// How many subroutine calls deep the program is
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// How deep calls can nest before a stack overflow. In RAM at most 24 fit
+    pub fn max_depth(&self) -> usize {
+        if self.in_memory {
+            self.max_depth.min((VIP_STACK_END - VIP_STACK_ADDRESS) / 2)
+        } else {
+            self.max_depth
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.min(MAX_DEPTH);
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
     }
 
-    pub fn pop(&mut self) -> usize {
-        self.stack.pop().unwrap()
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        self.in_memory = in_memory;
+    }
+
+    /// The return addresses, innermost call last
+    pub fn entries(&self) -> &[usize] {
+        &self.stack
+    }
+
+    /// For call stack views, each call in progress as the address of the call instruction and
+    /// the address it returns to, innermost first
+    pub fn frames(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.stack.iter().rev().map(|&return_address| (return_address.saturating_sub(2), return_address))
+    }
+
+    /// Where in RAM the return address of the call at depth (from 0) goes when the stack is in memory
+    pub fn memory_address(depth: usize) -> usize {
+        VIP_STACK_END - 2 * (depth + 1)
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.max_depth as u8);
+        state.bool(self.in_memory);
+        state.u8(self.stack.len() as u8);
+        for &address in &self.stack {
+            state.u16(address as u16);
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmulatorError> {
+        self.max_depth = state.u8()? as usize;
+        self.in_memory = state.bool()?;
+        let depth = state.u8()? as usize;
+        if depth > self.max_depth() {
+            return Err(invalid(format!("stack is {} deep, the most is {}", depth, self.max_depth())));
+        }
+        self.stack = (0..depth).map(|_| state.u16().map(|address| address as usize)).collect::<Result<_, _>>()?;
+        Ok(())
     }
 }
 
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new(SCHIP_STACK_DEPTH)
+    }
+}
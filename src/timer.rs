@@ -1,3 +1,6 @@
+use crate::error::EmulatorError;
+use crate::save_state::{StateReader, StateWriter};
+
 pub struct Timer {
     count: u8,
     last_tick: f32,
@@ -19,17 +22,37 @@ impl Timer {
         self.count
     }
 
-    pub fn update(&mut self, dt: f32) {
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.count);
+        state.f32(self.last_tick);
+    }
+
+    pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), EmulatorError> {
+        self.count = state.u8()?;
+        self.last_tick = state.f32()?;
+        Ok(())
+    }
+
+    /// Advances the timer by dt seconds, returning true if at least one 60Hz tick elapsed
+    pub fn update(&mut self, dt: f32) -> bool {
         self.last_tick += dt;
+        let mut ticked = false;
 
         let tick_length = 1.0 / 60.0; // 60Hz
 
         while self.last_tick >= tick_length {
             self.last_tick -= tick_length;
+            ticked = true;
+            self.tick();
+        }
+
+        ticked
+    }
 
-            if self.count > 0 {
-                self.count -= 1;
-            }
+    /// Count down once, for a 60Hz tick that comes from somewhere other than update
+    pub(crate) fn tick(&mut self) {
+        if self.count > 0 {
+            self.count -= 1;
         }
     }
 }
\ No newline at end of file
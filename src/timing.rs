@@ -0,0 +1,156 @@
+use crate::error::EmulatorError;
+use crate::opcode::Op;
+use crate::save_state::{invalid, StateReader, StateWriter};
+
+/// How fast instructions run compared to the 60Hz timers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Timing {
+    /// Every instruction takes the same time, instructions_per_frame of them to a 60Hz frame
+    #[default]
+    Instructions,
+    /// Every instruction takes as many machine cycles as on the COSMAC VIP, drawing waits for
+    /// the 60Hz interrupt, and the timers tick on the interrupt, every 3668 machine cycles
+    CosmacVip,
+}
+
+impl Timing {
+    pub const ALL: [Self; 2] = [Timing::Instructions, Timing::CosmacVip];
+
+    /// The name for `--timing`, movies and the DAP launch argument
+    pub fn name(&self) -> &'static str {
+        match self {
+            Timing::Instructions => "instructions",
+            Timing::CosmacVip => "cosmac-vip",
+        }
+    }
+
+    /// The timing with that name, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|value| value.name().eq_ignore_ascii_case(name))
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    pub(crate) fn load_state(state: &mut StateReader) -> Result<Self, EmulatorError> {
+        match state.u8()? {
+            0 => Ok(Timing::Instructions),
+            1 => Ok(Timing::CosmacVip),
+            timing => Err(invalid(format!("unknown timing {}", timing))),
+        }
+    }
+}
+
+/// The 1802 in the VIP runs at 1.7609 MHz, 8 clock pulses to a machine cycle
+pub(crate) const VIP_MACHINE_CYCLES_PER_SECOND: f32 = 1_760_900.0 / 8.0;
+/// Machine cycles between 60Hz interrupts
+pub(crate) const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// Machine cycles of each frame taken by the 1861 reading the screen out of memory, 8 bytes
+/// for each of the 128 scan lines, during which the interpreter doesn't run
+pub(crate) const VIP_DISPLAY_DMA_CYCLES: u32 = 1024;
+/// Machine cycles the interpreter spends fetching and decoding each instruction
+const VIP_FETCH_CYCLES: u32 = 68;
+/// FX33 divides by subtracting 100, then 10, then 1 until it would go below zero, this is the
+/// work done for each digit and each subtraction
+const VIP_BCD_CYCLES: u32 = 116;
+const VIP_BCD_SUBTRACT_CYCLES: u32 = 8;
+/// FX55/FX65 set up I and then copy one register each time round a loop
+const VIP_LOAD_STORE_CYCLES: u32 = 64;
+const VIP_LOAD_STORE_REGISTER_CYCLES: u32 = 14;
+/// DXYN finds the screen byte under X, Y after the interrupt, then each sprite row is shifted
+/// right a bit at a time into two bytes and XORed onto the screen
+const VIP_DRAW_CYCLES: u32 = 46;
+const VIP_DRAW_ROW_CYCLES: u32 = 38;
+const VIP_DRAW_SHIFT_CYCLES: u32 = 8;
+/// A row that turned a pixel off sets VF
+const VIP_DRAW_COLLISION_CYCLES: u32 = 4;
+/// A row that runs off the right edge has its screen address wrapped back to the row's start
+const VIP_DRAW_WRAP_CYCLES: u32 = 8;
+
+/// The machine cycles op takes on the VIP, including the fetch, given the registers it runs
+/// with. The execution times are the measured ones from
+/// https://jackson-s.me/2019/07/13/Chip-8-Instruction-Scheduling-and-Frequency.html
+/// converted from microseconds, the ones that loop depend on the operands. The wait in FX0A is
+/// emulated separately, DXYN's drawing is `vip_draw_cycles` after its wait, and the SUPER-CHIP
+/// and XO-CHIP instructions the VIP never had cost only the fetch
+pub(crate) fn vip_cycles(op: Op, registers: &[u8; 16]) -> u32 {
+    let execute = match op {
+        Op::ClearScreen => 24,
+        Op::Return | Op::Jump { .. } | Op::Call { .. } | Op::JumpWithOffset { .. } => 23,
+        Op::SkipIfEqual { .. } | Op::SkipIfNotEqual { .. } | Op::SetIndex { .. } => 12,
+        Op::SkipIfRegistersEqual { .. } | Op::SkipIfRegistersNotEqual { .. } => 16,
+        Op::SetImm { .. } => 6,
+        Op::AddImm { .. } => 10,
+        Op::Set { .. }
+        | Op::Or { .. }
+        | Op::And { .. }
+        | Op::Xor { .. }
+        | Op::Add { .. }
+        | Op::Sub { .. }
+        | Op::ShiftRight { .. }
+        | Op::SubReverse { .. }
+        | Op::ShiftLeft { .. } => 44,
+        Op::Random { .. } => 36,
+        Op::SkipIfKey { .. } | Op::SkipIfNotKey { .. } => 16,
+        Op::GetDelay { .. } | Op::SetDelay { .. } | Op::SetSound { .. } => 10,
+        Op::AddIndex { .. } => 19,
+        Op::FontCharacter { .. } => 20,
+        Op::BinaryCodedDecimal { x } => {
+            let value = registers[x] as u32;
+            let subtractions = value / 100 + value / 10 % 10 + value % 10;
+            VIP_BCD_CYCLES + subtractions * VIP_BCD_SUBTRACT_CYCLES
+        }
+        Op::Store { x } | Op::Load { x } => VIP_LOAD_STORE_CYCLES + (x as u32 + 1) * VIP_LOAD_STORE_REGISTER_CYCLES,
+        _ => 0,
+    };
+    VIP_FETCH_CYCLES + execute
+}
+
+/// The machine cycles the VIP spends drawing rows sprite rows at column x once DXYN's wait for
+/// the interrupt is over, of which collided_rows turned pixels off and wrapped_rows ran off the
+/// right edge
+pub(crate) fn vip_draw_cycles(x: u8, rows: u32, collided_rows: u32, wrapped_rows: u32) -> u32 {
+    let row = VIP_DRAW_ROW_CYCLES + (x % 8) as u32 * VIP_DRAW_SHIFT_CYCLES;
+    VIP_DRAW_CYCLES + rows * row + collided_rows * VIP_DRAW_COLLISION_CYCLES + wrapped_rows * VIP_DRAW_WRAP_CYCLES
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_cost_instructions() {
+        let registers = [0xFF; 16];
+        assert_eq!(vip_cycles(Op::SetImm { x: 0, nn: 1 }, &registers), 74);
+        assert_eq!(vip_cycles(Op::Add { x: 0, y: 1 }, &registers), 112);
+        assert_eq!(vip_cycles(Op::Call { nnn: 0x300 }, &registers), 91);
+        // only the fetch for an instruction the VIP didn't have
+        assert_eq!(vip_cycles(Op::ScrollRight, &registers), VIP_FETCH_CYCLES);
+    }
+
+    #[test]
+    fn binary_coded_decimal_takes_longer_for_bigger_digits() {
+        let mut registers = [0; 16];
+        let zero = vip_cycles(Op::BinaryCodedDecimal { x: 3 }, &registers);
+        assert_eq!(zero, 184);
+        registers[3] = 199;
+        assert_eq!(vip_cycles(Op::BinaryCodedDecimal { x: 3 }, &registers), zero + 19 * 8);
+    }
+
+    #[test]
+    fn load_and_store_take_longer_for_more_registers() {
+        let registers = [0; 16];
+        assert_eq!(vip_cycles(Op::Store { x: 0 }, &registers), 146);
+        assert_eq!(vip_cycles(Op::Load { x: 0xF }, &registers), 146 + 15 * 14);
+    }
+
+    #[test]
+    fn drawing_costs_rows_shifts_collisions_and_wraps() {
+        assert_eq!(vip_draw_cycles(0, 0, 0, 0), 46);
+        assert_eq!(vip_draw_cycles(8, 5, 0, 0), 46 + 5 * 38);
+        assert_eq!(vip_draw_cycles(3, 5, 0, 0), 46 + 5 * (38 + 3 * 8));
+        assert_eq!(vip_draw_cycles(60, 5, 2, 5), 46 + 5 * (38 + 4 * 8) + 2 * 4 + 5 * 8);
+    }
+}
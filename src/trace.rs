@@ -0,0 +1,53 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::computer::Computer;
+use crate::disassembler::{Disassembler, Syntax};
+use crate::opcode::Op;
+use crate::platform::Platform;
+
+/// Writes a line for every instruction executed, for diffing against other emulators:
+///
+/// ```text
+/// 00000042 0232 7301     ADD V3, 0x01         V 00 05 00 11 00 00 00 00 00 00 00 00 00 00 00 00 I 0246 DT 00 ST 00
+/// ```
+///
+/// The instruction count, the address, the raw opcode and the instruction, then V0 to VF, I and the
+/// timers after it ran, all in hex. Addresses in the instruction are never replaced by labels,
+/// so the same program gives the same text whatever ROM it came from.
+pub struct Trace {
+    output: Box<dyn Write>,
+    disassembler: Disassembler<'static>,
+}
+
+impl Trace {
+    pub fn new(output: impl Write + 'static) -> Self {
+        Self {
+            output: Box::new(output),
+            // with no ROM there are no labels
+            disassembler: Disassembler::new(&[], Platform::Chip8),
+        }
+    }
+
+    pub fn to_file(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    pub(crate) fn record(&mut self, computer: &Computer, address: usize, op: Op) -> io::Result<()> {
+        let raw: String = op.encode().iter().map(|byte| format!("{:02X}", byte)).collect();
+        let registers: Vec<String> = computer.registers().iter().map(|value| format!("{:02X}", value)).collect();
+        writeln!(
+            self.output,
+            "{:08} {:04X} {:<8} {:<20} V {} I {:04X} DT {:02X} ST {:02X}",
+            computer.instructions(),
+            address,
+            raw,
+            self.disassembler.format(op, Syntax::Classic),
+            registers.join(" "),
+            computer.index_register(),
+            computer.delay_timer(),
+            computer.sound_timer(),
+        )
+    }
+}
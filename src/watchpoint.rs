@@ -0,0 +1,83 @@
+use std::fmt;
+
+use crate::opcode::Op;
+
+/// How a program touched memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// The program counter reached the address
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// What to do when a watchpoint is hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchAction {
+    /// Pause the program
+    Break,
+    /// Note the hit and carry on
+    Log,
+}
+
+/// Watches the addresses start to end inclusive for the chosen kinds of access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    /// A watchpoint on writes only, the usual way to find what clobbers a variable
+    pub fn new(start: usize, end: usize, action: WatchAction) -> Self {
+        Self {
+            start,
+            end,
+            read: false,
+            write: true,
+            execute: false,
+            action,
+        }
+    }
+
+    pub fn matches(&self, address: usize, access: Access) -> bool {
+        let watched = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        watched && (self.start..=self.end).contains(&address)
+    }
+}
+
+/// A watched access, with the instruction that made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub access: Access,
+    pub address: usize,
+    /// The byte read or written, or the first byte of the instruction for an execute
+    pub value: u8,
+    pub program_counter: usize,
+    pub op: Op,
+    pub action: WatchAction,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:#05X} = {:#04X} by {:#05X} {:?}", self.access, self.address, self.value, self.program_counter, self.op)
+    }
+}
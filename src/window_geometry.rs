@@ -0,0 +1,98 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Where the window was and how big, remembered between runs in a small text file in the
+/// user's config directory:
+///
+/// ```text
+/// x 120
+/// y 80
+/// width 1280
+/// height 640
+/// fullscreen false
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowGeometry {
+    /// None to let the window be centred
+    pub position: Option<(i32, i32)>,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+}
+
+impl Default for WindowGeometry {
+    fn default() -> Self {
+        Self {
+            position: None,
+            width: 640,
+            height: 320,
+            fullscreen: false,
+        }
+    }
+}
+
+impl WindowGeometry {
+    /// The geometry saved by the last run, or the default if there is none
+    pub fn load() -> Self {
+        config_path()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = config_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        std::fs::write(path, self.to_string())
+    }
+
+    /// None if any of it is missing or malformed, an old file shouldn't put the window somewhere odd
+    fn parse(text: &str) -> Option<Self> {
+        let mut x = None;
+        let mut y = None;
+        let mut width = None;
+        let mut height = None;
+        let mut fullscreen = None;
+        for line in text.lines() {
+            match line.trim().split_once(' ')? {
+                ("x", value) => x = Some(value.trim().parse().ok()?),
+                ("y", value) => y = Some(value.trim().parse().ok()?),
+                ("width", value) => width = Some(value.trim().parse().ok()?),
+                ("height", value) => height = Some(value.trim().parse().ok()?),
+                ("fullscreen", value) => fullscreen = Some(value.trim().parse().ok()?),
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            position: Some((x?, y?)),
+            width: width.filter(|&width| width > 0)?,
+            height: height.filter(|&height| height > 0)?,
+            fullscreen: fullscreen?,
+        })
+    }
+}
+
+impl fmt::Display for WindowGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((x, y)) = self.position {
+            writeln!(f, "x {}", x)?;
+            writeln!(f, "y {}", y)?;
+        }
+        writeln!(f, "width {}", self.width)?;
+        writeln!(f, "height {}", self.height)?;
+        writeln!(f, "fullscreen {}", self.fullscreen)
+    }
+}
+
+/// rust-chip-8/window in $XDG_CONFIG_HOME, ~/.config or %APPDATA%
+fn config_path() -> Option<PathBuf> {
+    let directory = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(directory.join("rust-chip-8").join("window"))
+}